@@ -0,0 +1,239 @@
+use jsonwebtoken::jwk::JwkSet;
+use jsonwebtoken::{decode, decode_header, DecodingKey, Validation};
+use serde::Deserialize;
+
+use crate::Args;
+
+/// Verifies bearer tokens presented to the HTTP transport and maps them to
+/// a client identity.
+pub struct Authenticator {
+    /// `(token, client)` pairs from `--auth-tokens`.
+    tokens: Vec<(String, String)>,
+    jwks: Option<JwkSet>,
+    issuer: Option<String>,
+    audience: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Claims {
+    sub: String,
+}
+
+impl Authenticator {
+    /// Build from `--auth-tokens` / `--auth-jwks`. Returns `None` if neither
+    /// is configured.
+    pub fn from_args(args: &Args) -> Result<Option<Self>, String> {
+        if args.auth_tokens.is_none() && args.auth_jwks.is_none() {
+            return Ok(None);
+        }
+
+        let tokens = match &args.auth_tokens {
+            Some(path) => {
+                let text = std::fs::read_to_string(path)
+                    .map_err(|e| format!("Cannot read {}: {}", path, e))?;
+                parse_tokens(&text).map_err(|e| format!("{}: {}", path, e))?
+            }
+            None => Vec::new(),
+        };
+
+        let jwks = match &args.auth_jwks {
+            Some(path) => {
+                let text = std::fs::read_to_string(path)
+                    .map_err(|e| format!("Cannot read {}: {}", path, e))?;
+                let set: JwkSet = serde_json::from_str(&text)
+                    .map_err(|e| format!("{}: invalid JWKS: {}", path, e))?;
+                if set.keys.is_empty() {
+                    return Err(format!("{}: JWKS has no keys", path));
+                }
+                Some(set)
+            }
+            None => None,
+        };
+
+        Ok(Some(Self {
+            tokens,
+            jwks,
+            issuer: args.auth_issuer.clone(),
+            audience: args.auth_audience.clone(),
+        }))
+    }
+
+    /// Return the client identity for a bearer token: the name given in the
+    /// token file, or the JWT `sub` claim.
+    pub fn authenticate(&self, token: &str) -> Result<String, String> {
+        if let Some((_, client)) = self
+            .tokens
+            .iter()
+            .find(|(known, _)| constant_time_eq(known.as_bytes(), token.as_bytes()))
+        {
+            return Ok(client.clone());
+        }
+
+        match &self.jwks {
+            Some(jwks) => self.verify_jwt(jwks, token),
+            None => Err("unknown token".to_string()),
+        }
+    }
+
+    fn verify_jwt(&self, jwks: &JwkSet, token: &str) -> Result<String, String> {
+        let header = decode_header(token).map_err(|e| format!("malformed JWT: {}", e))?;
+        let jwk = match &header.kid {
+            Some(kid) => jwks
+                .find(kid)
+                .ok_or_else(|| format!("no JWKS key with kid '{}'", kid))?,
+            None if jwks.keys.len() == 1 => &jwks.keys[0],
+            None => return Err("JWT has no kid and the JWKS has several keys".to_string()),
+        };
+        let key = DecodingKey::from_jwk(jwk).map_err(|e| format!("unusable JWKS key: {}", e))?;
+
+        // The algorithm comes from the token header, but decode() refuses
+        // one that does not match the key's type, so an RSA public key can
+        // never be used as an HMAC secret.
+        let mut validation = Validation::new(header.alg);
+        match &self.issuer {
+            Some(issuer) => validation.set_issuer(&[issuer]),
+            None => validation.iss = None,
+        }
+        match &self.audience {
+            Some(audience) => validation.set_audience(&[audience]),
+            None => validation.validate_aud = false,
+        }
+
+        let data = decode::<Claims>(token, &key, &validation)
+            .map_err(|e| format!("JWT rejected: {}", e))?;
+        Ok(data.claims.sub)
+    }
+}
+
+/// Parse a token file: one `<client> <token>` pair per line, `#` comments.
+fn parse_tokens(text: &str) -> Result<Vec<(String, String)>, String> {
+    let mut tokens = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let mut parts = line.split_whitespace();
+        match (parts.next(), parts.next(), parts.next()) {
+            (Some(_), Some(token), None) if tokens.iter().any(|(t, _)| t == token) => {
+                return Err(format!("line {}: duplicate token", i + 1))
+            }
+            (Some(client), Some(token), None) => {
+                tokens.push((token.to_string(), client.to_string()))
+            }
+            _ => return Err(format!("line {}: expected '<client> <token>'", i + 1)),
+        }
+    }
+    Ok(tokens)
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use jsonwebtoken::{encode, Algorithm, EncodingKey, Header};
+    use serde_json::json;
+
+    const SECRET: &[u8] = b"0123456789abcdef0123456789abcdef";
+
+    fn authenticator() -> Authenticator {
+        // An HMAC key (`k` is SECRET in base64url) and an RSA public key.
+        let jwks = json!({ "keys": [
+            { "kty": "oct", "kid": "hmac", "k": "MDEyMzQ1Njc4OWFiY2RlZjAxMjM0NTY3ODlhYmNkZWY" },
+            { "kty": "RSA", "kid": "rsa", "e": "AQAB", "n": "xQ3Pe0QU2hekygdBWSuZIMMThVivoW0myWgcJYz7-O-Bf5qNoTxCIrsoerkhzCSvORIDFk2qPNSl-GiOLMLhQGLBboJpVDhfvNZbeM7OGKx2G5dwUmibeRXU93OjJqwgMb4hoQ0pnhFQL_BTfdhaEjYTpJQN0gInMUtm3h-8F7cra9P9C3DQ7n_xS2b1YeBARIWByxRZeEk7ahQZ3kmpPrnDm90agJ8bAIJo7k2Zjw6IOylO472qizDpFwy0XRnDCNcdRkUcSk2ENyZBguKj8H6olmHdfbgbFJ6qQpraRmREFj2n3GJf7E44J7TeVjngRDkjiDwA9OaciFP0RdhcxA" }
+        ] });
+        Authenticator {
+            tokens: vec![("s3cret".to_string(), "ci".to_string())],
+            jwks: Some(serde_json::from_value(jwks).unwrap()),
+            issuer: Some("https://issuer".to_string()),
+            audience: Some("prowl".to_string()),
+        }
+    }
+
+    fn jwt(kid: &str, claims: serde_json::Value) -> String {
+        let mut header = Header::new(Algorithm::HS256);
+        header.kid = Some(kid.to_string());
+        encode(&header, &claims, &EncodingKey::from_secret(SECRET)).unwrap()
+    }
+
+    fn claims(iss: &str, aud: &str, exp_in: i64) -> serde_json::Value {
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs() as i64;
+        json!({ "sub": "alice", "iss": iss, "aud": aud, "exp": now + exp_in })
+    }
+
+    #[test]
+    fn token_files_are_parsed() {
+        let tokens = parse_tokens("# clients\n\n  ci   s3cret  \nops other\nci rotated\n").unwrap();
+        assert_eq!(
+            tokens,
+            [
+                ("s3cret".to_string(), "ci".to_string()),
+                ("other".to_string(), "ops".to_string()),
+                ("rotated".to_string(), "ci".to_string()),
+            ]
+        );
+        assert_eq!(
+            parse_tokens("ci a\nops a").unwrap_err(),
+            "line 2: duplicate token"
+        );
+        assert_eq!(
+            parse_tokens("# x\nci").unwrap_err(),
+            "line 2: expected '<client> <token>'"
+        );
+        assert_eq!(
+            parse_tokens("ci a b").unwrap_err(),
+            "line 1: expected '<client> <token>'"
+        );
+    }
+
+    #[test]
+    fn tokens_compare_whole() {
+        assert!(constant_time_eq(b"s3cret", b"s3cret"));
+        assert!(!constant_time_eq(b"s3cret", b"s3creT"));
+        assert!(!constant_time_eq(b"s3cret", b"s3cre"));
+        assert!(!constant_time_eq(b"", b"s"));
+        assert!(constant_time_eq(b"", b""));
+    }
+
+    #[test]
+    fn jwts_are_verified() {
+        let auth = authenticator();
+        assert_eq!(auth.authenticate("s3cret").unwrap(), "ci");
+        assert_eq!(
+            auth.authenticate(&jwt("hmac", claims("https://issuer", "prowl", 600)))
+                .unwrap(),
+            "alice"
+        );
+
+        let rejected = |token: String| auth.authenticate(&token).unwrap_err();
+        assert_eq!(
+            rejected(jwt("other", claims("https://issuer", "prowl", 600))),
+            "no JWKS key with kid 'other'"
+        );
+        // An HMAC token naming the RSA key must not verify with it.
+        assert!(rejected(jwt("rsa", claims("https://issuer", "prowl", 600)))
+            .contains("InvalidAlgorithm"));
+        assert!(
+            rejected(jwt("hmac", claims("https://elsewhere", "prowl", 600)))
+                .contains("InvalidIssuer")
+        );
+        assert!(
+            rejected(jwt("hmac", claims("https://issuer", "other", 600)))
+                .contains("InvalidAudience")
+        );
+        assert!(
+            rejected(jwt("hmac", claims("https://issuer", "prowl", -600)))
+                .contains("ExpiredSignature")
+        );
+        assert_eq!(
+            rejected("s3cre".to_string()).split(':').next(),
+            Some("malformed JWT")
+        );
+    }
+}
@@ -1,62 +1,120 @@
-use claw::{AuthMethod, Client, Config};
-use tokio::net::TcpStream;
-use tokio_util::compat::TokioAsyncWriteCompatExt;
+use std::sync::Arc;
+use std::time::Duration;
 
-use crate::Args;
+use tracing::info;
 
-pub type TdsClient = Client<tokio_util::compat::Compat<TcpStream>>;
+use crate::pool::{Lease, Pool, PoolStats, TdsClient};
+use crate::progress::Progress;
+use crate::Args;
 
+/// A tool call's handle on the connection pool. A session is checked out
+/// on first use and returned by [`Connection::release`].
 pub struct Connection {
-    args: Args,
-    client: Option<TdsClient>,
+    pool: Arc<Pool>,
+    database: Option<String>,
+    lease: Option<Lease>,
+    progress: Option<Arc<Progress>>,
+    peer: Option<String>,
 }
 
 impl Connection {
-    pub fn new(args: Args) -> Self {
-        Self { args, client: None }
+    pub fn new(pool: Arc<Pool>) -> Self {
+        Self {
+            pool,
+            database: None,
+            lease: None,
+            progress: None,
+            peer: None,
+        }
     }
 
-    pub async fn get_client(&mut self) -> Result<&mut TdsClient, String> {
-        if self.client.is_none() {
-            self.connect().await?;
-        }
-        Ok(self.client.as_mut().unwrap())
+    /// Make the call on behalf of the client `peer`, which owns any state
+    /// it leaves behind, e.g. query cursors.
+    pub fn set_peer(&mut self, peer: &str) {
+        self.peer = Some(peer.to_string());
     }
 
-    async fn connect(&mut self) -> Result<(), String> {
-        let mut config = Config::new();
-        config.host(&self.args.host);
-        config.port(self.args.port);
-        config.authentication(AuthMethod::sql_server(&self.args.user, &self.args.password));
+    pub fn peer(&self) -> Option<&str> {
+        self.peer.as_deref()
+    }
 
-        if !self.args.no_trust_cert {
-            config.trust_cert();
+    /// Report rows streamed by this call through `progress`.
+    pub fn set_progress(&mut self, progress: Progress) {
+        self.progress = Some(Arc::new(progress));
+    }
+
+    pub fn progress(&self) -> Option<Arc<Progress>> {
+        self.progress.clone()
+    }
+
+    pub fn args(&self) -> &Args {
+        self.pool.args()
+    }
+
+    /// The global `--query-timeout`, if enabled.
+    pub fn query_timeout(&self) -> Option<Duration> {
+        match self.args().query_timeout {
+            0 => None,
+            secs => Some(Duration::from_secs(secs)),
         }
+    }
 
-        if let Some(ref db) = self.args.database {
-            config.database(db);
+    /// Pool counters plus (idle, in use, size).
+    pub fn pool_stats(&self) -> (PoolStats, usize, usize, usize) {
+        self.pool.stats()
+    }
+
+    /// Run this call's statements in `database` (`None` = the default
+    /// database), by checking out a session logged into it rather than
+    /// switching a shared session with `USE`.
+    pub fn set_database(&mut self, database: Option<&str>) {
+        if self.database.as_deref() != database {
+            self.database = database.map(|d| d.to_string());
+            self.lease = None;
         }
+    }
 
-        eprintln!("Connecting to {}:{}", self.args.host, self.args.port);
+    /// The database the checked-out session was logged into, if any.
+    pub fn current_database(&self) -> Option<&str> {
+        self.lease.as_ref().map(|l| l.session.home_database())
+    }
 
-        let client = Client::connect_with_redirect(config, |host, port| async move {
-            let addr = format!("{}:{}", host, port);
-            eprintln!("Connecting to {}", addr);
-            let tcp = TcpStream::connect(&addr).await?;
-            tcp.set_nodelay(true)?;
-            Ok(tcp.compat_write())
-        })
-        .await
-        .map_err(|e| format!("TDS connection failed: {}", e))?;
+    /// Whether a session is checked out.
+    pub fn has_session(&self) -> bool {
+        self.lease.is_some()
+    }
 
-        eprintln!("Connected successfully");
-        self.client = Some(client);
-        Ok(())
+    pub async fn get_client(&mut self) -> Result<&mut TdsClient, String> {
+        if self.lease.is_none() {
+            self.lease = Some(self.pool.acquire(self.database.as_deref()).await?);
+        }
+        Ok(self.lease.as_mut().unwrap().session.client())
     }
 
     pub async fn reconnect(&mut self) -> Result<&mut TdsClient, String> {
-        self.client = None;
-        self.connect().await?;
-        Ok(self.client.as_mut().unwrap())
+        self.lease = None;
+        self.get_client().await
+    }
+
+    /// Cancel whatever the current session is running.
+    ///
+    /// The client has no way to send a TDS attention, so the in-flight batch
+    /// is cancelled by closing its session: the server aborts the batch and
+    /// rolls back its transaction when it sees the connection drop. Nothing
+    /// is `KILL`ed, since the session id may already belong to someone else.
+    pub fn cancel(&mut self) {
+        if let Some(lease) = self.lease.take() {
+            info!(
+                spid = lease.session.spid(),
+                "Closed session to cancel its batch"
+            );
+        }
+    }
+
+    /// Return the checked-out session, if any, to the pool.
+    pub async fn release(mut self) {
+        if let Some(lease) = self.lease.take() {
+            self.pool.release(lease).await;
+        }
     }
 }
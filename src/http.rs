@@ -0,0 +1,486 @@
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use axum::body::Bytes;
+use axum::extract::State;
+use axum::http::{header, HeaderMap, HeaderValue, StatusCode};
+use axum::response::sse::{Event, KeepAlive, Sse};
+use axum::response::{IntoResponse, Response};
+use axum::routing::post;
+use axum::{Json, Router};
+use serde_json::{json, Value};
+use tokio::sync::mpsc;
+use tracing::{info, warn};
+
+use crate::auth::Authenticator;
+use crate::server::{random_id, Peer, Server, OUTBOX_CAPACITY, PROTOCOL_VERSIONS};
+
+const SESSION_HEADER: &str = "mcp-session-id";
+const VERSION_HEADER: &str = "mcp-protocol-version";
+
+/// Sessions with no request for this long, and no open SSE stream, are ended.
+const SESSION_IDLE_TIMEOUT: Duration = Duration::from_secs(1800);
+/// How often expired sessions are looked for.
+const REAP_INTERVAL: Duration = Duration::from_secs(60);
+
+/// An HTTP-level rejection, turned into a JSON-RPC error body by [`error`].
+type Rejection = (StatusCode, &'static str);
+
+/// An MCP Streamable HTTP session, created by `initialize`.
+struct HttpSession {
+    peer: Arc<Peer>,
+    /// Server-initiated messages waiting for the client's SSE stream. Taken
+    /// by the (single) open `GET` stream and put back when it closes.
+    events: Mutex<Option<mpsc::Receiver<Value>>>,
+    last_used: Mutex<Instant>,
+}
+
+struct HttpState {
+    server: Arc<Server>,
+    auth: Authenticator,
+    listen: SocketAddr,
+    sessions: Mutex<HashMap<String, Arc<HttpSession>>>,
+}
+
+/// Serve MCP over Streamable HTTP on a single `/mcp` endpoint. Every
+/// request must carry a bearer token accepted by `auth`.
+pub async fn serve(
+    server: Arc<Server>,
+    auth: Authenticator,
+    listen: SocketAddr,
+) -> std::io::Result<()> {
+    let state = Arc::new(HttpState {
+        server,
+        auth,
+        listen,
+        sessions: Mutex::new(HashMap::new()),
+    });
+
+    let reaper = state.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(REAP_INTERVAL);
+        loop {
+            interval.tick().await;
+            reaper.end_idle_sessions(SESSION_IDLE_TIMEOUT);
+        }
+    });
+
+    let listener = tokio::net::TcpListener::bind(listen).await?;
+    info!("Listening on http://{}/mcp", listen);
+    axum::serve(listener, router(state)).await
+}
+
+fn router(state: Arc<HttpState>) -> Router {
+    Router::new()
+        .route("/mcp", post(post_mcp).get(get_mcp).delete(delete_mcp))
+        .with_state(state)
+}
+
+impl HttpState {
+    fn new_session(&self, client: String) -> (String, Arc<HttpSession>) {
+        let id = random_id();
+        let (tx, rx) = mpsc::channel(OUTBOX_CAPACITY);
+        let session = Arc::new(HttpSession {
+            peer: Arc::new(Peer {
+                id: id.clone(),
+                client,
+                outbox: tx,
+            }),
+            events: Mutex::new(Some(rx)),
+            last_used: Mutex::new(Instant::now()),
+        });
+        self.sessions
+            .lock()
+            .unwrap()
+            .insert(id.clone(), session.clone());
+        info!(session = %id, client = %session.peer.client, "HTTP session started");
+        (id, session)
+    }
+
+    /// End sessions idle for `timeout`. A session with an open SSE stream
+    /// is still in use.
+    fn end_idle_sessions(&self, timeout: Duration) {
+        let mut ended = Vec::new();
+        self.sessions.lock().unwrap().retain(|_, session| {
+            let streaming = session.events.lock().unwrap().is_none();
+            let idle = session.last_used.lock().unwrap().elapsed() >= timeout;
+            if idle && !streaming {
+                ended.push(session.clone());
+            }
+            !idle || streaming
+        });
+        for session in ended {
+            self.server.forget_peer(&session.peer);
+            info!(session = %session.peer.id, "HTTP session expired");
+        }
+    }
+
+    /// Look up the session named by the `Mcp-Session-Id` header. A session
+    /// can only be used by the client that opened it.
+    fn session(&self, headers: &HeaderMap, client: &str) -> Result<Arc<HttpSession>, Rejection> {
+        let id = headers
+            .get(SESSION_HEADER)
+            .and_then(|v| v.to_str().ok())
+            .ok_or((StatusCode::BAD_REQUEST, "Missing Mcp-Session-Id header"))?;
+        let session = self
+            .sessions
+            .lock()
+            .unwrap()
+            .get(id)
+            .cloned()
+            .ok_or((StatusCode::NOT_FOUND, "Unknown or expired session"))?;
+        if session.peer.client != client {
+            warn!(
+                session = id,
+                owner = %session.peer.client,
+                client,
+                "HTTP session used by another client"
+            );
+            return Err((StatusCode::FORBIDDEN, "Session belongs to another client"));
+        }
+        *session.last_used.lock().unwrap() = Instant::now();
+        Ok(session)
+    }
+
+    /// Check the origin, protocol version and bearer token; returns the
+    /// client identity.
+    fn authorize(&self, headers: &HeaderMap) -> Result<String, Rejection> {
+        self.check_origin(headers)?;
+
+        // Sent by 2025-06-18 clients after initialize; older ones omit it.
+        if let Some(version) = headers.get(VERSION_HEADER) {
+            let supported = version
+                .to_str()
+                .is_ok_and(|v| PROTOCOL_VERSIONS.contains(&v));
+            if !supported {
+                return Err((StatusCode::BAD_REQUEST, "Unsupported MCP-Protocol-Version"));
+            }
+        }
+
+        let token = headers
+            .get(header::AUTHORIZATION)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| {
+                let (scheme, token) = v.split_once(' ')?;
+                scheme.eq_ignore_ascii_case("bearer").then(|| token.trim())
+            })
+            .ok_or((StatusCode::UNAUTHORIZED, "Missing bearer token"))?;
+
+        self.auth.authenticate(token).map_err(|e| {
+            warn!("HTTP authentication failed: {}", e);
+            (StatusCode::UNAUTHORIZED, "Invalid or expired bearer token")
+        })
+    }
+
+    /// Reject browser requests from other sites (DNS rebinding protection).
+    fn check_origin(&self, headers: &HeaderMap) -> Result<(), Rejection> {
+        let origin = match headers.get(header::ORIGIN).and_then(|v| v.to_str().ok()) {
+            Some(o) => o,
+            None => return Ok(()),
+        };
+        let authority = origin.split("://").nth(1).unwrap_or(origin);
+        let host = match authority.strip_prefix('[') {
+            Some(v6) => v6.split(']').next().unwrap_or_default(),
+            None => authority.split(':').next().unwrap_or_default(),
+        };
+        let listen_host = self.listen.ip().to_string();
+        if host == "localhost" || host == "127.0.0.1" || host == "::1" || host == listen_host {
+            Ok(())
+        } else {
+            Err((StatusCode::FORBIDDEN, "Origin not allowed"))
+        }
+    }
+}
+
+async fn post_mcp(
+    State(state): State<Arc<HttpState>>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Response {
+    let client = match state.authorize(&headers) {
+        Ok(client) => client,
+        Err(rejection) => return error(rejection),
+    };
+
+    let message: Value = match serde_json::from_slice(&body) {
+        Ok(v) => v,
+        Err(e) => {
+            let err_resp = json!({
+                "jsonrpc": "2.0",
+                "id": null,
+                "error": {
+                    "code": -32700,
+                    "message": format!("Parse error: {}", e)
+                }
+            });
+            return (StatusCode::BAD_REQUEST, Json(err_resp)).into_response();
+        }
+    };
+
+    let (batch, messages) = match message {
+        Value::Array(items) => (true, items),
+        other => (false, vec![other]),
+    };
+
+    let initialize = messages
+        .iter()
+        .any(|m| m.get("method").and_then(|v| v.as_str()) == Some("initialize"));
+    let (session_id, session) = if initialize {
+        state.new_session(client)
+    } else {
+        match state.session(&headers, &client) {
+            Ok(session) => (session.peer.id.clone(), session),
+            Err(rejection) => return error(rejection),
+        }
+    };
+
+    let server = &state.server;
+    let peer = &session.peer;
+    let mut requests = Vec::new();
+    for message in &messages {
+        if message.get("method").is_none() {
+            // A response to a server-initiated request; nothing to do.
+            continue;
+        }
+        if message.get("id").is_none() {
+            server.handle_notification(peer, message);
+        } else {
+            requests.push(server.handle_request(peer, message));
+        }
+    }
+
+    let responses: Vec<Value> = futures_util::future::join_all(requests)
+        .await
+        .into_iter()
+        .flatten()
+        .collect();
+
+    let mut resp = if responses.is_empty() {
+        StatusCode::ACCEPTED.into_response()
+    } else if batch {
+        Json(Value::Array(responses)).into_response()
+    } else {
+        Json(responses.into_iter().next().unwrap_or(Value::Null)).into_response()
+    };
+
+    if let Ok(value) = HeaderValue::from_str(&session_id) {
+        resp.headers_mut().insert(SESSION_HEADER, value);
+    }
+    resp
+}
+
+/// Open the SSE stream that carries server-initiated messages.
+async fn get_mcp(State(state): State<Arc<HttpState>>, headers: HeaderMap) -> Response {
+    let session = match state
+        .authorize(&headers)
+        .and_then(|client| state.session(&headers, &client))
+    {
+        Ok(session) => session,
+        Err(rejection) => return error(rejection),
+    };
+
+    let events = match session.events.lock().unwrap().take() {
+        Some(rx) => rx,
+        None => {
+            return error((
+                StatusCode::CONFLICT,
+                "An SSE stream is already open for this session",
+            ))
+        }
+    };
+
+    let stream = futures_util::stream::unfold(
+        EventStream {
+            session,
+            events: Some(events),
+        },
+        |mut stream| async move {
+            let message = stream.events.as_mut()?.recv().await?;
+            let event = Event::default().event("message").data(message.to_string());
+            Some((Ok::<_, Infallible>(event), stream))
+        },
+    );
+
+    Sse::new(stream)
+        .keep_alive(KeepAlive::default())
+        .into_response()
+}
+
+/// Owns a session's event receiver while its SSE stream is open, and hands
+/// it back when the client disconnects so a new stream can resume.
+struct EventStream {
+    session: Arc<HttpSession>,
+    events: Option<mpsc::Receiver<Value>>,
+}
+
+impl Drop for EventStream {
+    fn drop(&mut self) {
+        if let Some(events) = self.events.take() {
+            *self.session.events.lock().unwrap() = Some(events);
+        }
+        *self.session.last_used.lock().unwrap() = Instant::now();
+    }
+}
+
+/// Terminate a session.
+async fn delete_mcp(State(state): State<Arc<HttpState>>, headers: HeaderMap) -> Response {
+    let session = match state
+        .authorize(&headers)
+        .and_then(|client| state.session(&headers, &client))
+    {
+        Ok(session) => session,
+        Err(rejection) => return error(rejection),
+    };
+    state.sessions.lock().unwrap().remove(&session.peer.id);
+    state.server.forget_peer(&session.peer);
+    info!(session = %session.peer.id, "HTTP session ended");
+    StatusCode::NO_CONTENT.into_response()
+}
+
+fn error((status, message): Rejection) -> Response {
+    let body = json!({
+        "jsonrpc": "2.0",
+        "id": null,
+        "error": {
+            "code": -32600,
+            "message": message
+        }
+    });
+    let mut resp = (status, Json(body)).into_response();
+    if status == StatusCode::UNAUTHORIZED {
+        resp.headers_mut().insert(
+            header::WWW_AUTHENTICATE,
+            HeaderValue::from_static("Bearer realm=\"prowl\""),
+        );
+    }
+    resp
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::logging::LogSinks;
+    use crate::tools::Registry;
+    use crate::Args;
+    use axum::body::Body;
+    use axum::http::Request;
+    use clap::Parser;
+    use tower::ServiceExt;
+
+    /// A server that never connects: `initialize` needs no database.
+    fn state() -> Arc<HttpState> {
+        let tokens = std::env::temp_dir().join(format!("prowl-tokens-{}", random_id()));
+        std::fs::write(&tokens, "ci ci-token\nops ops-token\n").unwrap();
+        let args = Args::parse_from([
+            "prowl",
+            "--password",
+            "x",
+            "--auth-tokens",
+            tokens.to_str().unwrap(),
+        ]);
+        let auth = Authenticator::from_args(&args).unwrap().unwrap();
+        std::fs::remove_file(&tokens).unwrap();
+        let tools = Registry::builtin(&args);
+        Arc::new(HttpState {
+            server: Arc::new(Server::new(args, Arc::new(LogSinks::default()), tools)),
+            auth,
+            listen: "10.0.0.5:8080".parse().unwrap(),
+            sessions: Mutex::new(HashMap::new()),
+        })
+    }
+
+    fn origin(state: &HttpState, origin: Option<&str>) -> Result<(), Rejection> {
+        let mut headers = HeaderMap::new();
+        if let Some(origin) = origin {
+            headers.insert(header::ORIGIN, HeaderValue::from_str(origin).unwrap());
+        }
+        state.check_origin(&headers)
+    }
+
+    async fn post(
+        state: &Arc<HttpState>,
+        token: &str,
+        session: Option<&str>,
+        body: Value,
+    ) -> Response {
+        let mut request = Request::post("/mcp")
+            .header(header::AUTHORIZATION, format!("Bearer {}", token))
+            .header(header::CONTENT_TYPE, "application/json");
+        if let Some(session) = session {
+            request = request.header(SESSION_HEADER, session);
+        }
+        router(state.clone())
+            .oneshot(request.body(Body::from(body.to_string())).unwrap())
+            .await
+            .unwrap()
+    }
+
+    #[test]
+    fn only_local_origins_are_allowed() {
+        let state = state();
+        assert!(origin(&state, None).is_ok());
+        assert!(origin(&state, Some("http://localhost:3000")).is_ok());
+        assert!(origin(&state, Some("http://127.0.0.1")).is_ok());
+        assert!(origin(&state, Some("http://[::1]:8080")).is_ok());
+        assert!(origin(&state, Some("https://10.0.0.5:8080")).is_ok());
+        for foreign in [
+            "https://evil.example",
+            "http://localhost.evil.example",
+            "null",
+        ] {
+            assert_eq!(
+                origin(&state, Some(foreign)),
+                Err((StatusCode::FORBIDDEN, "Origin not allowed")),
+                "{}",
+                foreign
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn sessions_belong_to_their_client() {
+        let state = state();
+        let initialize = json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "method": "initialize",
+            "params": { "protocolVersion": "2025-06-18", "capabilities": {} }
+        });
+        let response = post(&state, "ci-token", None, initialize).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let session = response.headers()[SESSION_HEADER]
+            .to_str()
+            .unwrap()
+            .to_string();
+
+        let ping = json!({ "jsonrpc": "2.0", "id": 2, "method": "ping" });
+        let own = post(&state, "ci-token", Some(&session), ping.clone()).await;
+        assert_eq!(own.status(), StatusCode::OK);
+        let other = post(&state, "ops-token", Some(&session), ping.clone()).await;
+        assert_eq!(other.status(), StatusCode::FORBIDDEN);
+        let unknown = post(&state, "ci-token", Some("no-such-session"), ping.clone()).await;
+        assert_eq!(unknown.status(), StatusCode::NOT_FOUND);
+        let unauthorized = post(&state, "bad-token", Some(&session), ping).await;
+        assert_eq!(unauthorized.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[test]
+    fn idle_sessions_end_unless_streaming() {
+        let state = state();
+        let (idle, _) = state.new_session("ci".to_string());
+        let (streaming, session) = state.new_session("ci".to_string());
+        let events = session.events.lock().unwrap().take();
+
+        state.end_idle_sessions(SESSION_IDLE_TIMEOUT);
+        assert_eq!(state.sessions.lock().unwrap().len(), 2);
+
+        state.end_idle_sessions(Duration::ZERO);
+        let sessions = state.sessions.lock().unwrap();
+        assert!(!sessions.contains_key(&idle));
+        assert!(sessions.contains_key(&streaming));
+        drop(events);
+    }
+}
@@ -0,0 +1,186 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+use serde_json::{json, Map, Value};
+use tokio::sync::mpsc;
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id};
+use tracing::{Event, Level, Subscriber};
+use tracing_subscriber::filter::Targets;
+use tracing_subscriber::layer::{Context, SubscriberExt};
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::Layer;
+
+use crate::server::Peer;
+
+/// Level a client receives until it sends `logging/setLevel`.
+const DEFAULT_LEVEL: Level = Level::WARN;
+
+/// Clients that receive log events as `notifications/message`, keyed by
+/// peer id.
+#[derive(Default)]
+pub struct LogSinks {
+    sinks: Mutex<HashMap<String, Sink>>,
+}
+
+struct Sink {
+    level: Level,
+    outbox: mpsc::Sender<Value>,
+}
+
+/// Install the global tracing subscriber: a structured stderr log, plus a
+/// layer forwarding prowl's events to the MCP client whose request caused
+/// them. Events outside a request (startup, HTTP plumbing) stay on stderr.
+pub fn init() -> Arc<LogSinks> {
+    let sinks = Arc::new(LogSinks::default());
+
+    let stderr = tracing_subscriber::fmt::layer()
+        .with_writer(std::io::stderr)
+        .with_ansi(false)
+        .with_filter(
+            Targets::new()
+                .with_target("prowl", Level::INFO)
+                .with_default(Level::WARN),
+        );
+    let mcp = McpLayer {
+        sinks: sinks.clone(),
+    }
+    .with_filter(Targets::new().with_target("prowl", Level::DEBUG));
+
+    tracing_subscriber::registry().with(stderr).with(mcp).init();
+
+    sinks
+}
+
+impl LogSinks {
+    /// Start sending log events to `peer` at the default level.
+    pub fn subscribe(&self, peer: &Peer) {
+        self.sinks
+            .lock()
+            .unwrap()
+            .entry(peer.id.clone())
+            .or_insert_with(|| Sink {
+                level: DEFAULT_LEVEL,
+                outbox: peer.outbox.clone(),
+            });
+    }
+
+    /// Handle `logging/setLevel` with an MCP (syslog) level name.
+    pub fn set_level(&self, peer: &Peer, level: &str) -> Result<(), String> {
+        let level = match level {
+            "debug" => Level::DEBUG,
+            "info" | "notice" => Level::INFO,
+            "warning" => Level::WARN,
+            "error" | "critical" | "alert" | "emergency" => Level::ERROR,
+            other => return Err(format!("Unknown log level: {}", other)),
+        };
+        self.sinks.lock().unwrap().insert(
+            peer.id.clone(),
+            Sink {
+                level,
+                outbox: peer.outbox.clone(),
+            },
+        );
+        Ok(())
+    }
+
+    fn publish(&self, peer: &str, level: Level, logger: &str, data: Value) {
+        let mut sinks = self.sinks.lock().unwrap();
+        let sink = match sinks.get(peer) {
+            Some(sink) if level <= sink.level => sink,
+            _ => return,
+        };
+        let message = json!({
+            "jsonrpc": "2.0",
+            "method": "notifications/message",
+            "params": {
+                "level": mcp_level(level),
+                "logger": logger,
+                "data": data
+            }
+        });
+        // A full outbox drops the message; a closed one means the client is
+        // gone (e.g. an ended HTTP session).
+        if let Err(mpsc::error::TrySendError::Closed(_)) = sink.outbox.try_send(message) {
+            sinks.remove(peer);
+        }
+    }
+}
+
+fn mcp_level(level: Level) -> &'static str {
+    match level {
+        Level::ERROR => "error",
+        Level::WARN => "warning",
+        Level::INFO => "info",
+        _ => "debug",
+    }
+}
+
+/// Peer id recorded on a request span's `peer` field.
+struct PeerId(String);
+
+struct McpLayer {
+    sinks: Arc<LogSinks>,
+}
+
+impl<S> Layer<S> for McpLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        let mut fields = JsonFields::default();
+        attrs.record(&mut fields);
+        if let (Some(Value::String(peer)), Some(span)) = (fields.0.remove("peer"), ctx.span(id)) {
+            span.extensions_mut().insert(PeerId(peer));
+        }
+    }
+
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        let peer = ctx.event_scope(event).and_then(|scope| {
+            scope
+                .from_root()
+                .find_map(|span| span.extensions().get::<PeerId>().map(|p| p.0.clone()))
+        });
+        let peer = match peer {
+            Some(peer) => peer,
+            None => return,
+        };
+
+        let mut fields = JsonFields::default();
+        event.record(&mut fields);
+        let meta = event.metadata();
+        self.sinks
+            .publish(&peer, *meta.level(), meta.target(), Value::Object(fields.0));
+    }
+}
+
+/// Collects event or span fields as JSON.
+#[derive(Default)]
+struct JsonFields(Map<String, Value>);
+
+impl Visit for JsonFields {
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.0.insert(field.name().to_string(), Value::from(value));
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.0.insert(field.name().to_string(), Value::from(value));
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.0.insert(field.name().to_string(), Value::from(value));
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.insert(field.name().to_string(), Value::from(value));
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.0.insert(
+            field.name().to_string(),
+            Value::from(format!("{:?}", value)),
+        );
+    }
+}
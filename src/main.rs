@@ -1,9 +1,20 @@
+mod auth;
 mod connection;
+mod http;
+mod logging;
+mod pool;
+mod progress;
+mod prompts;
+mod resources;
 mod server;
 mod tools;
 
-use clap::Parser;
-use std::io::BufRead;
+use clap::{Parser, ValueEnum};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::sync::mpsc;
+use tracing::{error, info};
 
 /// 🐱 prowl — MCP server for SQL Server
 #[derive(Parser, Debug, Clone)]
@@ -32,25 +43,165 @@ pub struct Args {
     /// Disable trusting the server certificate
     #[arg(long, default_value = "false")]
     pub no_trust_cert: bool,
+
+    /// Run every query inside a transaction that is always rolled back
+    #[arg(long, default_value = "false")]
+    pub rollback_queries: bool,
+
+    /// Connect with ApplicationIntent=ReadOnly
+    #[arg(long, default_value = "false")]
+    pub read_only_intent: bool,
+
+    /// Cancel any batch still running after this many seconds (0 disables)
+    #[arg(long, default_value = "60")]
+    pub query_timeout: u64,
+
+    /// Stop reading a batch once its shown cells reach this many bytes (0 disables)
+    #[arg(long, default_value = "65536")]
+    pub max_bytes: usize,
+
+    /// Cut cell values longer than this many characters (0 disables)
+    #[arg(long, default_value = "2000")]
+    pub max_cell_chars: usize,
+
+    /// Maximum number of concurrent SQL Server sessions
+    #[arg(long, default_value = "4")]
+    pub pool_size: usize,
+
+    /// Close pooled sessions idle for this many seconds
+    #[arg(long, default_value = "300")]
+    pub pool_idle_timeout: u64,
+
+    /// Close pooled sessions older than this many seconds
+    #[arg(long, default_value = "1800")]
+    pub pool_max_lifetime: u64,
+
+    /// Log tool calls that take at least this many milliseconds (0 disables)
+    #[arg(long, default_value = "5000")]
+    pub slow_query_ms: u64,
+
+    /// How MCP clients reach prowl
+    #[arg(long, value_enum, default_value = "stdio")]
+    pub transport: Transport,
+
+    /// Address to listen on with --transport http
+    #[arg(long, default_value = "127.0.0.1:8080")]
+    pub listen: SocketAddr,
+
+    /// File of `<client> <token>` lines accepted as HTTP bearer tokens
+    #[arg(long)]
+    pub auth_tokens: Option<String>,
+
+    /// JWKS file whose keys verify JWT bearer tokens
+    #[arg(long)]
+    pub auth_jwks: Option<String>,
+
+    /// Required `iss` claim for JWT bearer tokens
+    #[arg(long)]
+    pub auth_issuer: Option<String>,
+
+    /// Required `aud` claim for JWT bearer tokens
+    #[arg(long)]
+    pub auth_audience: Option<String>,
+
+    /// TOML file of extra tools defined as parameterized SQL templates
+    #[arg(long, env = "PROWL_TOOLS_FILE")]
+    pub tools_file: Option<String>,
+
+    /// Directory `export_query` writes files to; the tool is only offered when set
+    #[arg(long, env = "PROWL_EXPORT_DIR")]
+    pub export_dir: Option<String>,
+
+    /// Fail an export, deleting its file, once the file would pass this many bytes (0 disables)
+    #[arg(long, default_value = "1073741824")]
+    pub max_export_bytes: u64,
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Transport {
+    /// Newline-delimited JSON-RPC on stdin/stdout
+    Stdio,
+    /// MCP Streamable HTTP
+    Http,
 }
 
 #[tokio::main]
 async fn main() {
     let args = Args::parse();
+    let log = logging::init();
 
-    eprintln!("prowl 🐱 MCP server starting...");
-    eprintln!("Connecting to {}:{} as {}", args.host, args.port, args.user);
+    info!("prowl 🐱 MCP server starting...");
+    info!("Connecting to {}:{} as {}", args.host, args.port, args.user);
 
-    let stdin = std::io::stdin();
-    let stdout = std::io::stdout();
+    let transport = args.transport;
+    let listen = args.listen;
+    let auth = match auth::Authenticator::from_args(&args) {
+        Ok(auth) => auth,
+        Err(e) => {
+            error!("Authentication setup failed: {}", e);
+            std::process::exit(1);
+        }
+    };
+    let tools = match tools::Registry::load(&args) {
+        Ok(tools) => tools,
+        Err(e) => {
+            error!("Loading tools failed: {}", e);
+            std::process::exit(1);
+        }
+    };
+    let srv = Arc::new(server::Server::new(args, log, tools));
+    if let Err(e) = srv.check_permissions().await {
+        error!("Permission check failed: {}", e);
+        std::process::exit(1);
+    }
 
-    let mut srv = server::Server::new(args);
+    match transport {
+        Transport::Stdio => run_stdio(srv).await,
+        Transport::Http => {
+            let auth = match auth {
+                Some(auth) => auth,
+                None => {
+                    error!("--transport http requires --auth-tokens or --auth-jwks");
+                    std::process::exit(1);
+                }
+            };
+            if let Err(e) = http::serve(srv, auth, listen).await {
+                error!("HTTP server error: {}", e);
+            }
+        }
+    }
 
-    for line in stdin.lock().lines() {
-        let line = match line {
-            Ok(l) => l,
+    info!("prowl shutting down 🐱");
+}
+
+async fn run_stdio(srv: Arc<server::Server>) {
+    // Requests run concurrently, so responses and notifications are
+    // funnelled through one writer to keep each JSON line intact.
+    let (tx, mut rx) = mpsc::channel::<serde_json::Value>(server::OUTBOX_CAPACITY);
+    let writer = tokio::spawn(async move {
+        let stdout = std::io::stdout();
+        while let Some(response) = rx.recv().await {
+            if let Err(e) = write_response(&stdout, &response) {
+                error!("stdout write error: {}", e);
+                break;
+            }
+        }
+    });
+
+    let peer = Arc::new(server::Peer {
+        id: "stdio".to_string(),
+        client: "stdio".to_string(),
+        outbox: tx,
+    });
+
+    let mut lines = BufReader::new(tokio::io::stdin()).lines();
+
+    loop {
+        let line = match lines.next_line().await {
+            Ok(Some(l)) => l,
+            Ok(None) => break,
             Err(e) => {
-                eprintln!("stdin read error: {}", e);
+                error!("stdin read error: {}", e);
                 break;
             }
         };
@@ -62,7 +213,7 @@ async fn main() {
         let request: serde_json::Value = match serde_json::from_str(&line) {
             Ok(v) => v,
             Err(e) => {
-                eprintln!("JSON parse error: {}", e);
+                error!("JSON parse error: {}", e);
                 let err_resp = serde_json::json!({
                     "jsonrpc": "2.0",
                     "id": null,
@@ -71,26 +222,29 @@ async fn main() {
                         "message": format!("Parse error: {}", e)
                     }
                 });
-                let _ = write_response(&stdout, &err_resp);
+                let _ = peer.outbox.send(err_resp).await;
                 continue;
             }
         };
 
-        // Notifications have no "id" — handle silently
+        // Notifications have no "id" and get no response
         if request.get("id").is_none() {
-            let method = request.get("method").and_then(|m| m.as_str()).unwrap_or("");
-            eprintln!("Notification: {}", method);
+            srv.handle_notification(&peer, &request);
             continue;
         }
 
-        let response = srv.handle_request(&request).await;
-        if let Err(e) = write_response(&stdout, &response) {
-            eprintln!("stdout write error: {}", e);
-            break;
-        }
+        let srv = srv.clone();
+        let peer = peer.clone();
+        tokio::spawn(async move {
+            if let Some(response) = srv.handle_request(&peer, &request).await {
+                let _ = peer.outbox.send(response).await;
+            }
+        });
     }
 
-    eprintln!("prowl shutting down 🐱");
+    // Let in-flight requests finish and flush their responses.
+    drop(peer);
+    let _ = writer.await;
 }
 
 fn write_response(
@@ -0,0 +1,300 @@
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use claw::{AuthMethod, Client, Config};
+use tokio::net::TcpStream;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tokio_util::compat::TokioAsyncWriteCompatExt;
+use tracing::{debug, info, warn};
+
+use crate::Args;
+
+pub type TdsClient = Client<tokio_util::compat::Compat<TcpStream>>;
+
+/// Idle sessions older than this are pinged before being handed out.
+const HEALTH_CHECK_AFTER: Duration = Duration::from_secs(30);
+
+/// Run when a session is returned: reports the database context and open
+/// transactions a call left behind, and turns off every session option the
+/// read-only guard lets a call (or prowl's own batch wrapper) change.
+const RESET_SESSION: &str = "SELECT DB_NAME(), @@TRANCOUNT;\n\
+     SET NOCOUNT OFF; SET XACT_ABORT OFF; SET TRANSACTION ISOLATION LEVEL READ COMMITTED;\n\
+     SET STATISTICS IO OFF; SET STATISTICS TIME OFF; SET STATISTICS XML OFF; SET STATISTICS PROFILE OFF;";
+
+/// Run before `RESET_SESSION`, one batch each, since a `SET SHOWPLAN_*`
+/// must be the only statement in its batch. A call cancelled inside the
+/// `query_plan` wrapper can leave showplan on, and then no later batch,
+/// `RESET_SESSION` included, would run at all.
+const RESET_SHOWPLAN: [&str; 2] = ["SET SHOWPLAN_TEXT OFF", "SET SHOWPLAN_XML OFF"];
+
+/// One TDS session to the server.
+pub struct Session {
+    client: TdsClient,
+    /// Server session id, for logs.
+    spid: i16,
+    /// Database requested when the session was opened (`None` = default).
+    database: Option<String>,
+    /// `DB_NAME()` right after login, to detect a leaked `USE`.
+    home_database: String,
+    created: Instant,
+    last_used: Instant,
+}
+
+impl Session {
+    async fn open(args: &Args, database: Option<&str>) -> Result<Self, String> {
+        let mut config = Config::new();
+        config.host(&args.host);
+        config.port(args.port);
+        config.authentication(AuthMethod::sql_server(&args.user, &args.password));
+
+        if !args.no_trust_cert {
+            config.trust_cert();
+        }
+
+        if args.read_only_intent {
+            config.readonly(true);
+        }
+
+        if let Some(db) = database.or(args.database.as_deref()) {
+            config.database(db);
+        }
+
+        debug!("Connecting to {}:{}", args.host, args.port);
+
+        let mut client = Client::connect_with_redirect(config, |host, port| async move {
+            let addr = format!("{}:{}", host, port);
+            debug!("Connecting to {}", addr);
+            let tcp = TcpStream::connect(&addr).await?;
+            tcp.set_nodelay(true)?;
+            Ok(tcp.compat_write())
+        })
+        .await
+        .map_err(|e| format!("TDS connection failed: {}", e))?;
+
+        let params: Vec<&dyn claw::IntoSql> = vec![];
+        let row = client
+            .execute("SELECT @@SPID, DB_NAME()", &params)
+            .await
+            .map_err(|e| format!("Session setup failed: {}", e))?
+            .into_row()
+            .await
+            .map_err(|e| format!("Session setup failed: {}", e))?
+            .ok_or_else(|| "Session setup returned no row".to_string())?;
+        let spid = row.get::<i16, _>(0).unwrap_or_default();
+        let home_database = row.get::<&str, _>(1).unwrap_or_default().to_string();
+
+        info!(spid, database = %home_database, "Connected to SQL Server");
+
+        let now = Instant::now();
+        Ok(Self {
+            client,
+            spid,
+            database: database.map(|d| d.to_string()),
+            home_database,
+            created: now,
+            last_used: now,
+        })
+    }
+
+    pub fn client(&mut self) -> &mut TdsClient {
+        &mut self.client
+    }
+
+    pub fn spid(&self) -> i16 {
+        self.spid
+    }
+
+    /// The database the session was logged into.
+    pub fn home_database(&self) -> &str {
+        &self.home_database
+    }
+
+    async fn current_database(&mut self) -> Option<String> {
+        let params: Vec<&dyn claw::IntoSql> = vec![];
+        let stream = self
+            .client
+            .execute("SELECT DB_NAME()", &params)
+            .await
+            .ok()?;
+        let row = stream.into_row().await.ok()??;
+        row.get::<&str, _>(0).map(|s| s.to_string())
+    }
+
+    /// Reset the session for the next call. Returns its database context
+    /// and transaction count from before the reset.
+    async fn reset(&mut self) -> Option<(String, i32)> {
+        let params: Vec<&dyn claw::IntoSql> = vec![];
+        for batch in RESET_SHOWPLAN {
+            let stream = self.client.execute(batch, &params).await.ok()?;
+            stream.into_results().await.ok()?;
+        }
+        let stream = self.client.execute(RESET_SESSION, &params).await.ok()?;
+        let row = stream.into_row().await.ok()??;
+        let database = row.get::<&str, _>(0)?.to_string();
+        Some((database, row.get::<i32, _>(1)?))
+    }
+}
+
+/// A session checked out of the pool. Dropping it closes the session and
+/// frees its slot; use [`Pool::release`] to return it for reuse.
+pub struct Lease {
+    pub session: Session,
+    _permit: OwnedSemaphorePermit,
+}
+
+/// Counters shown by the `pool_stats` tool.
+#[derive(Clone, Default)]
+pub struct PoolStats {
+    pub opened: u64,
+    pub reused: u64,
+    pub closed_idle: u64,
+    pub closed_expired: u64,
+    pub closed_unhealthy: u64,
+    pub closed_context: u64,
+    pub closed_transaction: u64,
+}
+
+/// A bounded pool of TDS sessions with per-database affinity.
+pub struct Pool {
+    args: Args,
+    idle: Mutex<Vec<Session>>,
+    permits: Arc<Semaphore>,
+    stats: Mutex<PoolStats>,
+}
+
+impl Pool {
+    pub fn new(args: Args) -> Self {
+        let size = args.pool_size.max(1);
+        Self {
+            args,
+            idle: Mutex::new(Vec::new()),
+            permits: Arc::new(Semaphore::new(size)),
+            stats: Mutex::new(PoolStats::default()),
+        }
+    }
+
+    pub fn args(&self) -> &Args {
+        &self.args
+    }
+
+    /// Check out a session for `database` (`None` = the default database),
+    /// waiting if every slot is in use.
+    pub async fn acquire(&self, database: Option<&str>) -> Result<Lease, String> {
+        let permit = self
+            .permits
+            .clone()
+            .acquire_owned()
+            .await
+            .map_err(|_| "Connection pool closed".to_string())?;
+
+        while let Some(mut session) = self.take_idle(database) {
+            if session.last_used.elapsed() >= HEALTH_CHECK_AFTER
+                && session.current_database().await.is_none()
+            {
+                warn!(spid = session.spid, "Closed unhealthy pooled session");
+                self.stats.lock().unwrap().closed_unhealthy += 1;
+                continue;
+            }
+            self.stats.lock().unwrap().reused += 1;
+            return Ok(Lease {
+                session,
+                _permit: permit,
+            });
+        }
+
+        self.make_room();
+        let session = Session::open(&self.args, database).await?;
+        self.stats.lock().unwrap().opened += 1;
+        Ok(Lease {
+            session,
+            _permit: permit,
+        })
+    }
+
+    /// Return a session for reuse, with its session options reset. Sessions
+    /// whose database context was changed by the call (e.g. `USE [db]`) or
+    /// that still have a transaction open are closed instead, so neither can
+    /// leak into the next call.
+    pub async fn release(&self, lease: Lease) {
+        let Lease {
+            mut session,
+            _permit,
+        } = lease;
+
+        match session.reset().await {
+            None => {
+                warn!(
+                    spid = session.spid,
+                    "Closed session that could not be reset"
+                );
+                self.stats.lock().unwrap().closed_unhealthy += 1;
+                return;
+            }
+            Some((database, _)) if database != session.home_database => {
+                info!(
+                    spid = session.spid,
+                    "Closed session whose database context was changed"
+                );
+                self.stats.lock().unwrap().closed_context += 1;
+                return;
+            }
+            Some((_, trancount)) if trancount > 0 => {
+                warn!(
+                    spid = session.spid,
+                    trancount, "Closed session with an open transaction"
+                );
+                self.stats.lock().unwrap().closed_transaction += 1;
+                return;
+            }
+            Some(_) => {}
+        }
+
+        session.last_used = Instant::now();
+        self.idle.lock().unwrap().push(session);
+    }
+
+    /// Pop the most recently used idle session for `database`, closing any
+    /// that have outlived the idle timeout or maximum lifetime.
+    fn take_idle(&self, database: Option<&str>) -> Option<Session> {
+        let idle_timeout = Duration::from_secs(self.args.pool_idle_timeout);
+        let max_lifetime = Duration::from_secs(self.args.pool_max_lifetime);
+        let mut idle = self.idle.lock().unwrap();
+        let mut stats = self.stats.lock().unwrap();
+
+        idle.retain(|s| {
+            if s.created.elapsed() >= max_lifetime {
+                stats.closed_expired += 1;
+                false
+            } else if s.last_used.elapsed() >= idle_timeout {
+                stats.closed_idle += 1;
+                false
+            } else {
+                true
+            }
+        });
+
+        let pos = idle
+            .iter()
+            .rposition(|s| s.database.as_deref() == database)?;
+        Some(idle.remove(pos))
+    }
+
+    /// Close the oldest idle session if opening another would exceed the
+    /// pool size, e.g. when idle sessions belong to other databases.
+    fn make_room(&self) {
+        let in_use = self.args.pool_size.max(1) - self.permits.available_permits();
+        let mut idle = self.idle.lock().unwrap();
+        if !idle.is_empty() && idle.len() + in_use > self.args.pool_size.max(1) {
+            idle.remove(0);
+            self.stats.lock().unwrap().closed_idle += 1;
+        }
+    }
+
+    /// Current counters plus (idle, in use, size).
+    pub fn stats(&self) -> (PoolStats, usize, usize, usize) {
+        let size = self.args.pool_size.max(1);
+        let idle = self.idle.lock().unwrap().len();
+        let in_use = size - self.permits.available_permits();
+        (self.stats.lock().unwrap().clone(), idle, in_use, size)
+    }
+}
@@ -0,0 +1,68 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use serde_json::{json, Value};
+use tokio::sync::mpsc;
+
+/// Minimum gap between two progress notifications for one call.
+const INTERVAL: Duration = Duration::from_millis(500);
+
+/// Sends `notifications/progress` for a tool call whose request carried
+/// `_meta.progressToken`. Counts rows across every batch the call runs, so
+/// `progress` only ever increases.
+pub struct Progress {
+    token: Value,
+    outbox: mpsc::Sender<Value>,
+    /// Include a human-readable `message` (MCP 2025-03-26 and later).
+    with_message: bool,
+    started: Instant,
+    rows: AtomicU64,
+    last_sent: Mutex<Instant>,
+}
+
+impl Progress {
+    pub fn new(token: Value, outbox: mpsc::Sender<Value>, with_message: bool) -> Self {
+        let now = Instant::now();
+        Self {
+            token,
+            outbox,
+            with_message,
+            started: now,
+            rows: AtomicU64::new(0),
+            last_sent: Mutex::new(now),
+        }
+    }
+
+    /// Record one streamed row of result set `result_set` (1-based), and
+    /// notify the client if enough time has passed since the last update.
+    pub fn row(&self, result_set: usize) {
+        let rows = self.rows.fetch_add(1, Ordering::Relaxed) + 1;
+
+        let mut last_sent = self.last_sent.lock().unwrap();
+        if last_sent.elapsed() < INTERVAL {
+            return;
+        }
+        *last_sent = Instant::now();
+        drop(last_sent);
+
+        let mut params = json!({
+            "progressToken": self.token,
+            "progress": rows
+        });
+        if self.with_message {
+            params["message"] = Value::from(format!(
+                "{} rows streamed, result set {}, {:.1}s elapsed",
+                rows,
+                result_set,
+                self.started.elapsed().as_secs_f64()
+            ));
+        }
+        // Dropped if the outbox is full; a later update supersedes it.
+        let _ = self.outbox.try_send(json!({
+            "jsonrpc": "2.0",
+            "method": "notifications/progress",
+            "params": params
+        }));
+    }
+}
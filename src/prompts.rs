@@ -0,0 +1,158 @@
+use serde_json::{json, Value};
+
+pub fn prompt_definitions() -> Value {
+    json!([
+        {
+            "name": "investigate_blocking",
+            "description": "Find what is blocking, who is waiting on it, and how to resolve it",
+            "arguments": [
+                { "name": "session_id", "description": "Session (SPID) to focus on, e.g. a blocked or head blocker session", "required": false }
+            ]
+        },
+        {
+            "name": "tune_query",
+            "description": "Analyze a query's execution plan, indexes and table sizes, and suggest improvements",
+            "arguments": [
+                { "name": "query", "description": "The SQL query to tune", "required": true },
+                { "name": "database", "description": "Database the query runs in", "required": false }
+            ]
+        },
+        {
+            "name": "explain_schema",
+            "description": "Explain a database's tables, relationships and likely purpose",
+            "arguments": [
+                { "name": "database", "description": "Database to explain", "required": true },
+                { "name": "schema", "description": "Limit to one schema (default: all)", "required": false }
+            ]
+        },
+        {
+            "name": "health_check",
+            "description": "Health check this SQL Server instance: configuration, load, blocking, waits and indexes",
+            "arguments": []
+        }
+    ])
+}
+
+/// Expand a prompt with its arguments into `{ description, messages }`.
+pub fn get_prompt(name: &str, arguments: &Value) -> Result<Value, String> {
+    let (description, text) = match name {
+        "investigate_blocking" => {
+            let focus = match opt_str(arguments, "session_id") {
+                Some(spid) => {
+                    let spid: i32 = spid
+                        .trim()
+                        .parse()
+                        .map_err(|_| format!("session_id must be a number, got '{}'", spid))?;
+                    format!(
+                        "Focus on session {spid}: find whether it is blocked or blocking, \
+                         and follow the chain to its head blocker.\n\n"
+                    )
+                }
+                None => String::new(),
+            };
+            (
+                "Investigate blocking",
+                format!(
+                    "Investigate blocking on this SQL Server instance.\n\n{focus}\
+                     1. Call `blocking_chains` to find blocked sessions and their head blockers.\n\
+                     2. Call `active_sessions` to see what each session in the chain is running, \
+                     its wait type, elapsed time and the host/program it comes from.\n\
+                     3. Use `query` on `sys.dm_tran_locks` and `sys.dm_exec_sessions` \
+                     (filtered with `@P1` parameters) to find which resources are held and how long \
+                     the head blocker's transaction has been open.\n\
+                     4. Explain the root cause in plain terms: which session, which statement, \
+                     which objects.\n\n\
+                     Recommend how to resolve it, e.g. committing or rolling back the open \
+                     transaction, an index that would shorten the locking statement, or a different \
+                     isolation level. prowl is read-only: do not try to `KILL` sessions, but say \
+                     which one a DBA would kill as a last resort and what would be rolled back."
+                ),
+            )
+        }
+        "tune_query" => {
+            let query = req_str(arguments, "query")?;
+            let database = match opt_str(arguments, "database") {
+                Some(db) => format!(" in database `{}`", db),
+                None => String::new(),
+            };
+            let db_arg = match opt_str(arguments, "database") {
+                Some(db) => format!(" with `database` set to `{}`", db),
+                None => String::new(),
+            };
+            (
+                "Tune a query",
+                format!(
+                    "Help me tune this query{database}:\n\n```sql\n{query}\n```\n\n\
+                     1. Call `query_plan`{db_arg} and read the plan: look for scans of large \
+                     tables, key lookups, implicit conversions, sorts and hash joins over large \
+                     estimated row counts.\n\
+                     2. Call `describe_table` for each table the query touches to check column types \
+                     and keys.\n\
+                     3. Call `table_sizes` and `index_usage` for the database to see how big the \
+                     tables are and which indexes the optimizer reports as missing.\n\n\
+                     Then suggest concrete changes, most impactful first: rewrites of the query, \
+                     indexes to add (as `CREATE INDEX` statements), and statistics to update. \
+                     Explain why each would help. Do not run any changes; prowl is read-only."
+                ),
+            )
+        }
+        "explain_schema" => {
+            let database = req_str(arguments, "database")?;
+            let scope = match opt_str(arguments, "schema") {
+                Some(schema) => format!("the `{}` schema of `{}`", schema, database),
+                None => format!("the `{}` database", database),
+            };
+            (
+                "Explain a schema",
+                format!(
+                    "Explain {scope} to me.\n\n\
+                     1. Call `list_tables` for `{database}` (or read the `mssql://{database}` \
+                     resource) to see the tables and views.\n\
+                     2. Call `table_sizes` to see which tables hold the most data.\n\
+                     3. Call `describe_table` for the main tables to learn their columns, primary \
+                     keys and foreign keys.\n\n\
+                     Then describe what the schema is for, its main entities and how they relate \
+                     (a short text diagram of the foreign keys helps), naming conventions, and \
+                     anything unusual such as tables without primary keys or very wide tables."
+                ),
+            )
+        }
+        "health_check" => (
+            "Health check this instance",
+            "Run a health check of this SQL Server instance.\n\n\
+             1. Call `server_info` for version, edition and configuration.\n\
+             2. Call `active_sessions` and `blocking_chains` to see current load and blocking.\n\
+             3. Use `query` on `sys.dm_os_wait_stats` (excluding benign idle waits) to find the \
+             top waits since the last restart.\n\
+             4. Call `list_databases`, then `table_sizes` and `index_usage` for the largest \
+             user databases.\n\
+             5. Use `query` on `sys.databases` to check recovery models, `page_verify_option` and \
+             `is_auto_shrink_on`.\n\n\
+             Summarize the findings as a short report: what is healthy, what needs attention \
+             (most urgent first), and the recommended fix for each issue."
+                .to_string(),
+        ),
+        _ => return Err(format!("Unknown prompt: {}", name)),
+    };
+
+    Ok(json!({
+        "description": description,
+        "messages": [{
+            "role": "user",
+            "content": {
+                "type": "text",
+                "text": text
+            }
+        }]
+    }))
+}
+
+fn opt_str<'a>(args: &'a Value, key: &str) -> Option<&'a str> {
+    args.get(key)
+        .and_then(|v| v.as_str())
+        .filter(|s| !s.trim().is_empty())
+}
+
+fn req_str<'a>(args: &'a Value, key: &str) -> Result<&'a str, String> {
+    opt_str(args, key).ok_or_else(|| format!("Missing required argument: {}", key))
+}
@@ -0,0 +1,487 @@
+use serde_json::{json, Map, Value};
+
+use crate::connection::Connection;
+use crate::tools::schema::{fetch_result_sets, in_database, Limits, ResultSet};
+use crate::tools::value::cell_to_json;
+
+const SCHEME: &str = "mssql://";
+const MIME_TYPE: &str = "application/json";
+/// Resources returned per `resources/list` page.
+const PAGE_SIZE: usize = 100;
+
+/// A parsed `mssql://{database}[/{schema}[/{table}]]` URI.
+enum ResourceUri {
+    Database(String),
+    Schema(String, String),
+    Table(String, String, String),
+}
+
+pub fn templates() -> Value {
+    json!([
+        {
+            "uriTemplate": "mssql://{database}",
+            "name": "Database",
+            "description": "Tables and views in a database, with row counts",
+            "mimeType": MIME_TYPE
+        },
+        {
+            "uriTemplate": "mssql://{database}/{schema}",
+            "name": "Schema",
+            "description": "Tables and views in a schema, with row counts",
+            "mimeType": MIME_TYPE
+        },
+        {
+            "uriTemplate": "mssql://{database}/{schema}/{table}",
+            "name": "Table",
+            "description": "A table's columns, primary key, indexes, foreign keys and row count",
+            "mimeType": MIME_TYPE
+        }
+    ])
+}
+
+/// Every accessible database, plus the tables of the default `--database`
+/// if one is configured, one page at a time. Returns the page and the
+/// `nextCursor`, if there are more. Errors from parsing `cursor` are
+/// prefixed `Invalid cursor`.
+pub async fn list(
+    conn: &mut Connection,
+    cursor: Option<&str>,
+) -> Result<(Value, Option<String>), String> {
+    let start = parse_cursor(cursor)?;
+
+    let timeout = conn.query_timeout();
+    let batch = fetch_result_sets(
+        conn,
+        "SELECT name FROM sys.databases WHERE HAS_DBACCESS(name) = 1 ORDER BY name",
+        &[],
+        Limits::default(),
+        timeout,
+    )
+    .await?;
+
+    let mut resources: Vec<Value> = Vec::new();
+    for db in rows(batch.sets.first()) {
+        let name = text(&db, "name");
+        resources.push(json!({
+            "uri": database_uri(&name),
+            "name": name,
+            "description": format!("Tables and views in {}", name),
+            "mimeType": MIME_TYPE
+        }));
+    }
+
+    if let Some(database) = conn.args().database.clone() {
+        for table in tables(conn, &database, None).await? {
+            let schema = text(&table, "schema");
+            let name = text(&table, "name");
+            resources.push(json!({
+                "uri": table_uri(&database, &schema, &name),
+                "name": format!("{}.{}.{}", database, schema, name),
+                "description": format!("Columns, keys and indexes of {}.{}", schema, name),
+                "mimeType": MIME_TYPE
+            }));
+        }
+    }
+
+    let (page, next) = page(resources, start);
+    Ok((Value::Array(page), next))
+}
+
+/// A `resources/list` cursor is the index of the page's first resource.
+fn parse_cursor(cursor: Option<&str>) -> Result<usize, String> {
+    match cursor {
+        None => Ok(0),
+        Some(c) => c.parse().map_err(|_| format!("Invalid cursor '{}'", c)),
+    }
+}
+
+/// The page of `items` starting at `start`, and the cursor of the next one.
+fn page(items: Vec<Value>, start: usize) -> (Vec<Value>, Option<String>) {
+    let end = start.saturating_add(PAGE_SIZE).min(items.len());
+    let next = (end < items.len()).then(|| end.to_string());
+    let page = items
+        .into_iter()
+        .skip(start)
+        .take(end.saturating_sub(start));
+    (page.collect(), next)
+}
+
+/// Read a resource. Errors from parsing `uri` are prefixed `Invalid URI`.
+pub async fn read(conn: &mut Connection, uri: &str) -> Result<Value, String> {
+    let body = match parse_uri(uri)? {
+        ResourceUri::Database(database) => {
+            let tables = tables(conn, &database, None).await?;
+            json!({ "database": database, "tables": tables })
+        }
+        ResourceUri::Schema(database, schema) => {
+            let tables = tables(conn, &database, Some(&schema)).await?;
+            json!({ "database": database, "schema": schema, "tables": tables })
+        }
+        ResourceUri::Table(database, schema, table) => {
+            describe(conn, &database, &schema, &table).await?
+        }
+    };
+
+    let text = serde_json::to_string_pretty(&body).map_err(|e| e.to_string())?;
+    Ok(json!([{ "uri": uri, "mimeType": MIME_TYPE, "text": text }]))
+}
+
+/// Tables and views in `database`, optionally limited to one schema.
+async fn tables(
+    conn: &mut Connection,
+    database: &str,
+    schema: Option<&str>,
+) -> Result<Vec<Map<String, Value>>, String> {
+    let sql = in_database(
+        r#"IF @schema IS NOT NULL AND SCHEMA_ID(@schema) IS NULL
+    RAISERROR('Schema %s does not exist', 16, 1, @schema);
+ELSE
+SELECT
+    s.name AS [schema],
+    o.name AS name,
+    CASE o.type WHEN 'U' THEN 'table' ELSE 'view' END AS type,
+    (SELECT SUM(p.rows) FROM sys.partitions p
+     WHERE p.object_id = o.object_id AND p.index_id IN (0, 1)) AS row_count
+FROM sys.objects o
+JOIN sys.schemas s ON s.schema_id = o.schema_id
+WHERE o.type IN ('U', 'V') AND o.is_ms_shipped = 0
+    AND (@schema IS NULL OR s.name = @schema)
+ORDER BY s.name, o.name"#,
+        &["@schema sysname"],
+    );
+
+    let timeout = conn.query_timeout();
+    let batch = fetch_result_sets(
+        conn,
+        &sql,
+        &[&database, &schema],
+        Limits::default(),
+        timeout,
+    )
+    .await?;
+
+    Ok(rows(batch.sets.first())
+        .into_iter()
+        .map(|mut table| {
+            let uri = table_uri(database, &text(&table, "schema"), &text(&table, "name"));
+            table.insert("uri".to_string(), Value::from(uri));
+            table
+        })
+        .collect())
+}
+
+/// The same information as `describe_table`, plus indexes and row count.
+async fn describe(
+    conn: &mut Connection,
+    database: &str,
+    schema: &str,
+    table: &str,
+) -> Result<Value, String> {
+    let sql = in_database(
+        r#"DECLARE @id int = OBJECT_ID(QUOTENAME(@schema) + N'.' + QUOTENAME(@table));
+IF @id IS NULL OR OBJECTPROPERTY(@id, 'IsMSShipped') = 1
+    RAISERROR('Table %s.%s does not exist', 16, 1, @schema, @table);
+ELSE
+BEGIN
+    SELECT
+        CASE o.type WHEN 'U' THEN 'table' WHEN 'V' THEN 'view' ELSE o.type_desc END AS type,
+        (SELECT SUM(p.rows) FROM sys.partitions p
+         WHERE p.object_id = @id AND p.index_id IN (0, 1)) AS row_count
+    FROM sys.objects o
+    WHERE o.object_id = @id;
+
+    SELECT
+        c.name,
+        TYPE_NAME(c.user_type_id) AS type,
+        CASE WHEN c.max_length = -1 THEN NULL
+             WHEN TYPE_NAME(c.system_type_id) IN ('nchar', 'nvarchar') THEN c.max_length / 2
+             WHEN TYPE_NAME(c.system_type_id) IN ('char', 'varchar', 'binary', 'varbinary') THEN c.max_length
+        END AS max_length,
+        c.precision,
+        c.scale,
+        c.is_nullable,
+        c.is_identity,
+        c.is_computed,
+        dc.definition AS [default]
+    FROM sys.columns c
+    LEFT JOIN sys.default_constraints dc ON dc.object_id = c.default_object_id
+    WHERE c.object_id = @id
+    ORDER BY c.column_id;
+
+    SELECT
+        i.name,
+        i.type_desc AS type,
+        i.is_primary_key,
+        i.is_unique,
+        COL_NAME(ic.object_id, ic.column_id) AS [column],
+        ic.is_included_column
+    FROM sys.indexes i
+    JOIN sys.index_columns ic ON ic.object_id = i.object_id AND ic.index_id = i.index_id
+    WHERE i.object_id = @id
+    ORDER BY i.index_id, ic.is_included_column, ic.key_ordinal, ic.index_column_id;
+
+    SELECT
+        fk.name,
+        COL_NAME(fkc.parent_object_id, fkc.parent_column_id) AS [column],
+        OBJECT_SCHEMA_NAME(fkc.referenced_object_id) AS ref_schema,
+        OBJECT_NAME(fkc.referenced_object_id) AS ref_table,
+        COL_NAME(fkc.referenced_object_id, fkc.referenced_column_id) AS ref_column
+    FROM sys.foreign_keys fk
+    JOIN sys.foreign_key_columns fkc ON fk.object_id = fkc.constraint_object_id
+    WHERE fk.parent_object_id = @id
+    ORDER BY fk.name, fkc.constraint_column_id;
+END"#,
+        &["@schema sysname", "@table sysname"],
+    );
+
+    let timeout = conn.query_timeout();
+    let batch = fetch_result_sets(
+        conn,
+        &sql,
+        &[&database, &schema, &table],
+        Limits::default(),
+        timeout,
+    )
+    .await?;
+    if batch.timed_out {
+        return Err("Query timed out and was cancelled".to_string());
+    }
+    if batch.sets.len() < 4 {
+        return Err(format!(
+            "Table {}.{} not found or not accessible in {}",
+            schema, table, database
+        ));
+    }
+
+    let info = rows(batch.sets.first())
+        .into_iter()
+        .next()
+        .unwrap_or_default();
+    let columns = rows(batch.sets.get(1));
+
+    // One row per index column; fold them into one object per index.
+    let mut indexes: Vec<Map<String, Value>> = Vec::new();
+    let mut primary_key: Vec<Value> = Vec::new();
+    for row in rows(batch.sets.get(2)) {
+        let name = row.get("name").cloned().unwrap_or(Value::Null);
+        if indexes.last().and_then(|i| i.get("name")) != Some(&name) {
+            let mut index = Map::new();
+            index.insert("name".to_string(), name);
+            index.insert("type".to_string(), field(&row, "type"));
+            index.insert("primary_key".to_string(), field(&row, "is_primary_key"));
+            index.insert("unique".to_string(), field(&row, "is_unique"));
+            index.insert("columns".to_string(), json!([]));
+            index.insert("included_columns".to_string(), json!([]));
+            indexes.push(index);
+        }
+        let index = indexes.last_mut().unwrap();
+        let list = if row.get("is_included_column") == Some(&Value::Bool(true)) {
+            "included_columns"
+        } else {
+            "columns"
+        };
+        if let Some(Value::Array(cols)) = index.get_mut(list) {
+            cols.push(field(&row, "column"));
+        }
+        if row.get("is_primary_key") == Some(&Value::Bool(true)) {
+            primary_key.push(field(&row, "column"));
+        }
+    }
+
+    let mut foreign_keys: Vec<Value> = Vec::new();
+    for row in rows(batch.sets.get(3)) {
+        let name = field(&row, "name");
+        if foreign_keys.last().and_then(|fk| fk.get("name")) != Some(&name) {
+            foreign_keys.push(json!({
+                "name": name,
+                "columns": [],
+                "references": {
+                    "schema": field(&row, "ref_schema"),
+                    "table": field(&row, "ref_table"),
+                    "columns": []
+                }
+            }));
+        }
+        let fk = foreign_keys.last_mut().unwrap();
+        if let Some(Value::Array(cols)) = fk.get_mut("columns") {
+            cols.push(field(&row, "column"));
+        }
+        if let Some(Value::Array(cols)) = fk.pointer_mut("/references/columns") {
+            cols.push(field(&row, "ref_column"));
+        }
+    }
+
+    Ok(json!({
+        "database": database,
+        "schema": schema,
+        "table": table,
+        "type": field(&info, "type"),
+        "row_count": field(&info, "row_count"),
+        "columns": columns,
+        "primary_key": primary_key,
+        "indexes": indexes,
+        "foreign_keys": foreign_keys
+    }))
+}
+
+/// A result set as JSON objects keyed by column name.
+fn rows(set: Option<&ResultSet>) -> Vec<Map<String, Value>> {
+    let set = match set {
+        Some(set) => set,
+        None => return Vec::new(),
+    };
+    set.rows
+        .iter()
+        .map(|row| {
+            row.cells()
+                .map(|(col, data)| {
+                    (
+                        col.name().to_string(),
+                        cell_to_json(col.column_type(), data),
+                    )
+                })
+                .collect()
+        })
+        .collect()
+}
+
+fn field(row: &Map<String, Value>, name: &str) -> Value {
+    row.get(name).cloned().unwrap_or(Value::Null)
+}
+
+fn text(row: &Map<String, Value>, name: &str) -> String {
+    row.get(name)
+        .and_then(|v| v.as_str())
+        .unwrap_or_default()
+        .to_string()
+}
+
+fn parse_uri(uri: &str) -> Result<ResourceUri, String> {
+    let path = uri
+        .strip_prefix(SCHEME)
+        .ok_or_else(|| format!("Invalid URI '{}': expected {}...", uri, SCHEME))?;
+    let parts = path
+        .trim_end_matches('/')
+        .split('/')
+        .map(|p| percent_decode(p).ok_or_else(|| format!("Invalid URI '{}': bad escape", uri)))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    if parts.iter().any(|p| p.is_empty()) {
+        return Err(format!("Invalid URI '{}': empty path segment", uri));
+    }
+
+    let mut parts = parts.into_iter();
+    match (parts.next(), parts.next(), parts.next(), parts.next()) {
+        (Some(db), None, None, None) => Ok(ResourceUri::Database(db)),
+        (Some(db), Some(schema), None, None) => Ok(ResourceUri::Schema(db, schema)),
+        (Some(db), Some(schema), Some(table), None) => Ok(ResourceUri::Table(db, schema, table)),
+        _ => Err(format!(
+            "Invalid URI '{}': expected mssql://{{database}}/{{schema}}/{{table}}",
+            uri
+        )),
+    }
+}
+
+fn database_uri(database: &str) -> String {
+    format!("{}{}", SCHEME, percent_encode(database))
+}
+
+fn table_uri(database: &str, schema: &str, table: &str) -> String {
+    format!(
+        "{}{}/{}/{}",
+        SCHEME,
+        percent_encode(database),
+        percent_encode(schema),
+        percent_encode(table)
+    )
+}
+
+/// Escape everything but RFC 3986 unreserved characters, so names with
+/// spaces or slashes survive the round trip.
+fn percent_encode(segment: &str) -> String {
+    let mut out = String::with_capacity(segment.len());
+    for b in segment.bytes() {
+        if b.is_ascii_alphanumeric() || matches!(b, b'-' | b'.' | b'_' | b'~') {
+            out.push(b as char);
+        } else {
+            out.push_str(&format!("%{:02X}", b));
+        }
+    }
+    out
+}
+
+fn percent_decode(segment: &str) -> Option<String> {
+    let bytes = segment.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = segment.get(i + 1..i + 3)?;
+            out.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        } else {
+            out.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(out).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn uris_round_trip_escaped_names() {
+        let uri = table_uri("my db", "dbo", "a/b%");
+        assert_eq!(uri, "mssql://my%20db/dbo/a%2Fb%25");
+        match parse_uri(&uri).unwrap() {
+            ResourceUri::Table(db, schema, table) => {
+                assert_eq!(
+                    (db.as_str(), schema.as_str(), table.as_str()),
+                    ("my db", "dbo", "a/b%")
+                );
+            }
+            _ => panic!("expected a table URI"),
+        }
+        assert!(matches!(parse_uri("mssql://db/"), Ok(ResourceUri::Database(db)) if db == "db"));
+        assert!(matches!(
+            parse_uri("mssql://db/s"),
+            Ok(ResourceUri::Schema(..))
+        ));
+    }
+
+    #[test]
+    fn bad_uris_are_invalid() {
+        for uri in [
+            "http://db",
+            "mssql://",
+            "mssql://db//t",
+            "mssql://a/b/c/d",
+            "mssql://%zz",
+        ] {
+            assert!(
+                parse_uri(uri).err().unwrap().starts_with("Invalid URI"),
+                "{}",
+                uri
+            );
+        }
+    }
+
+    #[test]
+    fn list_pages_end_with_no_cursor() {
+        let items: Vec<Value> = (0..250).map(Value::from).collect();
+        let (first, next) = page(items.clone(), parse_cursor(None).unwrap());
+        assert_eq!((first.len(), first[0].clone()), (100, Value::from(0)));
+        assert_eq!(next.as_deref(), Some("100"));
+
+        let (last, next) = page(items.clone(), parse_cursor(Some("200")).unwrap());
+        assert_eq!((last.len(), last[0].clone()), (50, Value::from(200)));
+        assert_eq!(next, None);
+
+        assert_eq!(page(items, 300), (Vec::new(), None));
+        assert!(parse_cursor(Some("x"))
+            .unwrap_err()
+            .starts_with("Invalid cursor"));
+    }
+}
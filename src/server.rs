@@ -1,47 +1,217 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
 use serde_json::{json, Value};
+use tokio::sync::{mpsc, oneshot};
+use tracing::{info, info_span, warn, Instrument};
 
 use crate::connection::Connection;
-use crate::tools;
+use crate::logging::LogSinks;
+use crate::pool::Pool;
+use crate::progress::Progress;
+use crate::prompts;
+use crate::resources;
+use crate::tools::{query, Registry};
 use crate::Args;
 
+/// A connected MCP client: the single stdio peer, or one HTTP session.
+pub struct Peer {
+    pub id: String,
+    /// Who the client authenticated as (`stdio` for the local pipe).
+    pub client: String,
+    /// Delivers server-initiated messages (notifications) to the client.
+    pub outbox: mpsc::Sender<Value>,
+}
+
+/// Messages a peer's outbox holds. Notifications that do not fit, e.g. while
+/// an HTTP client has no SSE stream open, are dropped.
+pub const OUTBOX_CAPACITY: usize = 256;
+
+/// 128 bits from the OS random number generator, hex-encoded: unguessable
+/// ids for HTTP sessions and result cursors.
+pub fn random_id() -> String {
+    let mut bytes = [0u8; 16];
+    getrandom::fill(&mut bytes).expect("OS random number generator failed");
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// MCP revisions prowl speaks, newest first.
+pub const PROTOCOL_VERSIONS: &[&str] = &["2025-06-18", "2025-03-26", "2024-11-05"];
+
+/// First revision with tool `outputSchema` and `structuredContent`.
+const STRUCTURED_OUTPUT_SINCE: &str = "2025-06-18";
+/// First revision with tool `annotations`.
+const TOOL_ANNOTATIONS_SINCE: &str = "2025-03-26";
+/// First revision with a `message` on progress notifications.
+const PROGRESS_MESSAGE_SINCE: &str = "2025-03-26";
+
+/// What a peer negotiated in `initialize`.
+struct ClientState {
+    protocol_version: &'static str,
+}
+
 pub struct Server {
-    connection: Connection,
+    pool: Arc<Pool>,
+    log: Arc<LogSinks>,
+    tools: Registry,
+    /// Negotiated protocol state, keyed by peer id.
+    clients: Mutex<HashMap<String, ClientState>>,
+    /// Cancellation senders for running tool calls, keyed by peer id and
+    /// serialized request id.
+    in_flight: Mutex<HashMap<String, oneshot::Sender<()>>>,
 }
 
 impl Server {
-    pub fn new(args: Args) -> Self {
+    pub fn new(args: Args, log: Arc<LogSinks>, tools: Registry) -> Self {
         Self {
-            connection: Connection::new(args),
+            pool: Arc::new(Pool::new(args)),
+            log,
+            tools,
+            clients: Mutex::new(HashMap::new()),
+            in_flight: Mutex::new(HashMap::new()),
         }
     }
 
-    pub async fn handle_request(&mut self, request: &Value) -> Value {
-        let id = request.get("id").cloned().unwrap_or(Value::Null);
+    /// Check that the login has the permissions the configured options need.
+    /// An unreachable server is only warned about, so prowl can start first.
+    pub async fn check_permissions(&self) -> Result<(), String> {
+        if !self.pool.args().rollback_queries {
+            return Ok(());
+        }
+        let mut conn = Connection::new(self.pool.clone());
+        let result = query::can_view_server_state(&mut conn).await;
+        conn.release().await;
+        match result {
+            Ok(true) => Ok(()),
+            Ok(false) => Err(format!(
+                "--rollback-queries needs VIEW SERVER STATE for {} to check whether a batch modified data",
+                self.pool.args().user
+            )),
+            Err(e) => {
+                warn!("Could not check permissions for --rollback-queries: {}", e);
+                Ok(())
+            }
+        }
+    }
+
+    /// Handle a request. Returns `None` if it was cancelled by the client,
+    /// in which case no response must be sent.
+    pub async fn handle_request(&self, peer: &Peer, request: &Value) -> Option<Value> {
         let method = request.get("method").and_then(|m| m.as_str()).unwrap_or("");
+        // Events logged while handling the request are sent to this peer.
+        let span = info_span!("request", peer = %peer.id, method);
+        self.dispatch_request(peer, method, request)
+            .instrument(span)
+            .await
+    }
+
+    async fn dispatch_request(&self, peer: &Peer, method: &str, request: &Value) -> Option<Value> {
+        let id = request.get("id").cloned().unwrap_or(Value::Null);
 
         match method {
-            "initialize" => self.handle_initialize(id),
-            "tools/list" => self.handle_tools_list(id),
-            "tools/call" => self.handle_tools_call(id, request).await,
-            _ => json!({
+            "initialize" => Some(self.handle_initialize(peer, id, request)),
+            "ping" => Some(json!({
                 "jsonrpc": "2.0",
                 "id": id,
-                "error": {
-                    "code": -32601,
-                    "message": format!("Method not found: {}", method)
-                }
-            }),
+                "result": {}
+            })),
+            "logging/setLevel" => Some(self.handle_set_level(peer, id, request)),
+            "tools/list" => Some(self.handle_tools_list(peer, id)),
+            "tools/call" => self.handle_tools_call(peer, id, request).await,
+            "resources/list" => Some(self.handle_resources_list(id, request).await),
+            "resources/templates/list" => Some(self.handle_resource_templates_list(id)),
+            "resources/read" => Some(self.handle_resources_read(id, request).await),
+            "prompts/list" => Some(self.handle_prompts_list(id)),
+            "prompts/get" => Some(self.handle_prompts_get(id, request)),
+            _ => Some(rpc_error(
+                id,
+                -32601,
+                &format!("Method not found: {}", method),
+            )),
+        }
+    }
+
+    pub fn handle_notification(&self, peer: &Peer, request: &Value) {
+        let method = request.get("method").and_then(|m| m.as_str()).unwrap_or("");
+        info!(peer = %peer.id, method, "Notification");
+
+        if method == "notifications/initialized" {
+            if let Some(client) = self.clients.lock().unwrap().get(&peer.id) {
+                info!(
+                    peer = %peer.id,
+                    protocol_version = client.protocol_version,
+                    "Client initialized"
+                );
+            }
+        } else if method == "notifications/cancelled" {
+            let request_id = request
+                .get("params")
+                .and_then(|p| p.get("requestId"))
+                .cloned()
+                .unwrap_or(Value::Null);
+            let sender = self
+                .in_flight
+                .lock()
+                .unwrap()
+                .remove(&in_flight_key(peer, &request_id));
+            if let Some(sender) = sender {
+                let _ = sender.send(());
+            }
         }
     }
 
-    fn handle_initialize(&self, id: Value) -> Value {
+    /// The protocol revision negotiated with `peer`. Peers that skipped
+    /// `initialize` get the oldest one.
+    fn protocol_version(&self, peer: &Peer) -> &'static str {
+        self.clients
+            .lock()
+            .unwrap()
+            .get(&peer.id)
+            .map(|c| c.protocol_version)
+            .unwrap_or(PROTOCOL_VERSIONS[PROTOCOL_VERSIONS.len() - 1])
+    }
+
+    /// Forget a peer's negotiated state, e.g. when its HTTP session ends.
+    pub fn forget_peer(&self, peer: &Peer) {
+        self.clients.lock().unwrap().remove(&peer.id);
+    }
+
+    fn handle_initialize(&self, peer: &Peer, id: Value, request: &Value) -> Value {
+        let params = request.get("params").cloned().unwrap_or(Value::Null);
+        let requested = params
+            .get("protocolVersion")
+            .and_then(|v| v.as_str())
+            .unwrap_or("");
+        // Agree to the client's revision if we speak it; otherwise offer our
+        // latest and let the client decide whether to continue.
+        let protocol_version = PROTOCOL_VERSIONS
+            .iter()
+            .find(|v| **v == requested)
+            .copied()
+            .unwrap_or(PROTOCOL_VERSIONS[0]);
+        let capabilities = params.get("capabilities").cloned().unwrap_or(json!({}));
+        let client_info = params.get("clientInfo").cloned().unwrap_or(json!({}));
+        // prowl uses no client capability (roots, sampling, elicitation);
+        // they are only logged.
+        info!(requested, protocol_version, %client_info, %capabilities, "Initialize");
+
+        self.clients
+            .lock()
+            .unwrap()
+            .insert(peer.id.clone(), ClientState { protocol_version });
+        self.log.subscribe(peer);
+
         json!({
             "jsonrpc": "2.0",
             "id": id,
             "result": {
-                "protocolVersion": "2024-11-05",
+                "protocolVersion": protocol_version,
                 "capabilities": {
-                    "tools": {}
+                    "tools": {},
+                    "resources": {},
+                    "prompts": {},
+                    "logging": {}
                 },
                 "serverInfo": {
                     "name": "prowl",
@@ -51,36 +221,200 @@ impl Server {
         })
     }
 
-    fn handle_tools_list(&self, id: Value) -> Value {
+    fn handle_set_level(&self, peer: &Peer, id: Value, request: &Value) -> Value {
+        let level = request
+            .get("params")
+            .and_then(|p| p.get("level"))
+            .and_then(|l| l.as_str())
+            .unwrap_or("");
+        match self.log.set_level(peer, level) {
+            Ok(()) => json!({
+                "jsonrpc": "2.0",
+                "id": id,
+                "result": {}
+            }),
+            Err(e) => rpc_error(id, -32602, &e),
+        }
+    }
+
+    fn handle_tools_list(&self, peer: &Peer, id: Value) -> Value {
+        let protocol_version = self.protocol_version(peer);
+        let mut definitions = self.tools.definitions();
+        if let Value::Array(list) = &mut definitions {
+            for tool in list.iter_mut().filter_map(|t| t.as_object_mut()) {
+                if protocol_version < STRUCTURED_OUTPUT_SINCE {
+                    tool.remove("outputSchema");
+                }
+                if protocol_version < TOOL_ANNOTATIONS_SINCE {
+                    tool.remove("annotations");
+                }
+            }
+        }
+
         json!({
             "jsonrpc": "2.0",
             "id": id,
             "result": {
-                "tools": tools::tool_definitions()
+                "tools": definitions
             }
         })
     }
 
-    async fn handle_tools_call(&mut self, id: Value, request: &Value) -> Value {
-        let params = request.get("params").cloned().unwrap_or(Value::Null);
-        let tool_name = params.get("name").and_then(|n| n.as_str()).unwrap_or("");
-        let arguments = params.get("arguments").cloned().unwrap_or(json!({}));
+    async fn handle_resources_list(&self, id: Value, request: &Value) -> Value {
+        let cursor = request
+            .get("params")
+            .and_then(|p| p.get("cursor"))
+            .and_then(|c| c.as_str());
+
+        let mut conn = Connection::new(self.pool.clone());
+        let result = resources::list(&mut conn, cursor).await;
+        conn.release().await;
+
+        match result {
+            Ok((resources, next_cursor)) => {
+                let mut result = json!({ "resources": resources });
+                if let Some(next_cursor) = next_cursor {
+                    result["nextCursor"] = Value::from(next_cursor);
+                }
+                json!({
+                    "jsonrpc": "2.0",
+                    "id": id,
+                    "result": result
+                })
+            }
+            Err(e) if e.starts_with("Invalid cursor") => rpc_error(id, -32602, &e),
+            Err(e) => rpc_error(id, -32603, &e),
+        }
+    }
+
+    fn handle_resource_templates_list(&self, id: Value) -> Value {
+        json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "result": {
+                "resourceTemplates": resources::templates()
+            }
+        })
+    }
 
-        eprintln!("Tool call: {} with {:?}", tool_name, arguments);
+    async fn handle_resources_read(&self, id: Value, request: &Value) -> Value {
+        let uri = request
+            .get("params")
+            .and_then(|p| p.get("uri"))
+            .and_then(|u| u.as_str())
+            .unwrap_or("");
+        info!(uri, "Resource read");
 
-        let result = tools::dispatch(tool_name, &arguments, &mut self.connection).await;
+        let mut conn = Connection::new(self.pool.clone());
+        let result = resources::read(&mut conn, uri).await;
+        conn.release().await;
 
         match result {
-            Ok(text) => json!({
+            Ok(contents) => json!({
                 "jsonrpc": "2.0",
                 "id": id,
                 "result": {
-                    "content": [{
-                        "type": "text",
-                        "text": text
-                    }]
+                    "contents": contents
                 }
             }),
+            Err(e) => rpc_error(id, resource_read_error_code(&e), &e),
+        }
+    }
+
+    fn handle_prompts_list(&self, id: Value) -> Value {
+        json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "result": {
+                "prompts": prompts::prompt_definitions()
+            }
+        })
+    }
+
+    fn handle_prompts_get(&self, id: Value, request: &Value) -> Value {
+        let params = request.get("params").cloned().unwrap_or(Value::Null);
+        let name = params.get("name").and_then(|n| n.as_str()).unwrap_or("");
+        let arguments = params.get("arguments").cloned().unwrap_or(json!({}));
+
+        match prompts::get_prompt(name, &arguments) {
+            Ok(result) => json!({
+                "jsonrpc": "2.0",
+                "id": id,
+                "result": result
+            }),
+            Err(e) => rpc_error(id, -32602, &e),
+        }
+    }
+
+    async fn handle_tools_call(&self, peer: &Peer, id: Value, request: &Value) -> Option<Value> {
+        let params = request.get("params").cloned().unwrap_or(Value::Null);
+        let tool_name = params.get("name").and_then(|n| n.as_str()).unwrap_or("");
+        let arguments = params.get("arguments").cloned().unwrap_or(json!({}));
+
+        info!(tool = tool_name, client = %peer.client, %arguments, "Tool call");
+
+        let key = in_flight_key(peer, &id);
+        let (cancel_tx, mut cancel_rx) = oneshot::channel();
+        self.in_flight
+            .lock()
+            .unwrap()
+            .insert(key.clone(), cancel_tx);
+
+        let started = Instant::now();
+        let mut conn = Connection::new(self.pool.clone());
+        conn.set_peer(&peer.id);
+        let protocol_version = self.protocol_version(peer);
+        if let Some(token) = params.get("_meta").and_then(|m| m.get("progressToken")) {
+            conn.set_progress(Progress::new(
+                token.clone(),
+                peer.outbox.clone(),
+                protocol_version >= PROGRESS_MESSAGE_SINCE,
+            ));
+        }
+        let result = tokio::select! {
+            result = self.tools.dispatch(tool_name, &arguments, &mut conn) => Some(result),
+            Ok(()) = &mut cancel_rx => None,
+        };
+        self.in_flight.lock().unwrap().remove(&key);
+
+        let result = match result {
+            Some(result) => result,
+            None => {
+                info!(tool = tool_name, "Tool call cancelled by client");
+                conn.cancel();
+                conn.release().await;
+                return None;
+            }
+        };
+        conn.release().await;
+
+        let elapsed = started.elapsed();
+        let slow = Duration::from_millis(self.pool.args().slow_query_ms);
+        if !slow.is_zero() && elapsed >= slow {
+            let elapsed_ms = elapsed.as_millis() as u64;
+            match arguments.get("sql").and_then(|s| s.as_str()) {
+                Some(sql) => warn!(tool = tool_name, elapsed_ms, sql, "Slow query"),
+                None => warn!(tool = tool_name, elapsed_ms, "Slow tool call"),
+            }
+        }
+
+        Some(match result {
+            Ok(output) => {
+                let content: Vec<Value> = output
+                    .content
+                    .into_iter()
+                    .map(|text| json!({ "type": "text", "text": text }))
+                    .collect();
+                let mut result = json!({ "content": content });
+                if protocol_version >= STRUCTURED_OUTPUT_SINCE {
+                    result["structuredContent"] = output.structured;
+                }
+                json!({
+                    "jsonrpc": "2.0",
+                    "id": id,
+                    "result": result
+                })
+            }
             Err(e) => json!({
                 "jsonrpc": "2.0",
                 "id": id,
@@ -92,6 +426,65 @@ impl Server {
                     "isError": true
                 }
             }),
+        })
+    }
+}
+
+fn rpc_error(id: Value, code: i64, message: &str) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "id": id,
+        "error": {
+            "code": code,
+            "message": message
         }
+    })
+}
+
+fn in_flight_key(peer: &Peer, request_id: &Value) -> String {
+    format!("{}:{}", peer.id, request_id)
+}
+
+/// JSON-RPC error code for a failed `resources/read`: -32002 (resource not
+/// found) only when the table is missing, not for a connection or query
+/// failure.
+fn resource_read_error_code(error: &str) -> i64 {
+    if error.starts_with("Invalid URI") {
+        -32602
+    } else if error.starts_with("Table ") && error.contains(" not found or not accessible in ") {
+        -32002
+    } else {
+        -32603
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn random_ids_are_128_bit_hex() {
+        let a = random_id();
+        let b = random_id();
+        assert_eq!(a.len(), 32);
+        assert!(a.chars().all(|c| c.is_ascii_hexdigit()));
+        assert_ne!(a, b);
+    }
+
+    #[test]
+    fn only_a_missing_table_is_resource_not_found() {
+        assert_eq!(resource_read_error_code("Invalid URI: x"), -32602);
+        assert_eq!(
+            resource_read_error_code("Table dbo.T not found or not accessible in app"),
+            -32002
+        );
+        assert_eq!(
+            resource_read_error_code("Connection failed: login timeout"),
+            -32603
+        );
+        assert_eq!(
+            resource_read_error_code("Query error: Table dbo.T is locked"),
+            -32603
+        );
     }
 }
@@ -0,0 +1,457 @@
+use async_trait::async_trait;
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+
+use crate::connection::Connection;
+use crate::tools::params::{parse_typed, SqlParam};
+use crate::tools::schema::query_to_output;
+use crate::tools::{
+    input_schema, parse_args, result_sets_schema, tsql, DynTool, FormatArgs, ToolOutput,
+    LEXER_CHECKED,
+};
+
+/// The `--tools-file` format: a list of `[[tool]]` tables.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ToolsFile {
+    #[serde(default)]
+    tool: Vec<TemplateConfig>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TemplateConfig {
+    name: String,
+    title: Option<String>,
+    description: String,
+    /// Database to run in (default: the server's --database).
+    database: Option<String>,
+    #[serde(default = "default_read_only")]
+    read_only: bool,
+    sql: String,
+    #[serde(default)]
+    params: Vec<ParamConfig>,
+}
+
+fn default_read_only() -> bool {
+    true
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ParamConfig {
+    name: String,
+    /// SQL Server type, e.g. `int` or `nvarchar(128)`.
+    #[serde(rename = "type")]
+    sql_type: String,
+    description: Option<String>,
+    /// Makes the parameter optional.
+    default: Option<toml::Value>,
+}
+
+/// A tool defined by a SQL template in `--tools-file`. Its parameters are
+/// declared with their SQL types and bound through `sp_executesql`, never
+/// spliced into the SQL.
+pub struct TemplateTool {
+    name: String,
+    title: String,
+    description: String,
+    database: Option<String>,
+    sql: String,
+    params: Vec<TemplateParam>,
+}
+
+struct TemplateParam {
+    name: String,
+    sql_type: String,
+    description: Option<String>,
+    default: Option<Value>,
+}
+
+/// Load the template tools from a TOML file.
+pub fn load(path: &str) -> Result<Vec<TemplateTool>, String> {
+    let text = std::fs::read_to_string(path).map_err(|e| format!("Cannot read {}: {}", path, e))?;
+    let file: ToolsFile = toml::from_str(&text).map_err(|e| format!("{}: {}", path, e))?;
+    file.tool
+        .into_iter()
+        .map(|config| {
+            let name = config.name.clone();
+            TemplateTool::new(config).map_err(|e| format!("{}: tool '{}': {}", path, name, e))
+        })
+        .collect()
+}
+
+impl TemplateTool {
+    fn new(config: TemplateConfig) -> Result<Self, String> {
+        if !is_identifier(&config.name) {
+            return Err("name must be letters, digits and underscores".to_string());
+        }
+        if config.read_only {
+            tsql::check_read_only(&config.sql).map_err(|reason| {
+                format!(
+                    "sql is not read-only ({}); set read_only = false to allow writes",
+                    reason
+                )
+            })?;
+        }
+
+        let mut params = Vec::new();
+        for param in config.params {
+            let name = param.name.trim_start_matches('@').to_string();
+            if !is_identifier(&name) {
+                return Err(format!("invalid parameter name '{}'", param.name));
+            }
+            if name == "format" {
+                return Err("parameter name 'format' is reserved for the output format".to_string());
+            }
+            if params.iter().any(|p: &TemplateParam| p.name == name) {
+                return Err(format!("duplicate parameter '{}'", name));
+            }
+            let sql_type = param.sql_type.trim().to_lowercase();
+            // The type is written into the sp_executesql declaration list,
+            // so it must be a plain type name like decimal(10, 2).
+            if !is_type_name(&sql_type) {
+                return Err(format!("parameter '{}': invalid type '{}'", name, sql_type));
+            }
+            let default = param
+                .default
+                .map(|v| serde_json::to_value(v).map_err(|e| e.to_string()))
+                .transpose()?;
+            let check = default.clone().unwrap_or(Value::Null);
+            parse_typed(&check, &sql_type).map_err(|e| format!("parameter '{}': {}", name, e))?;
+            params.push(TemplateParam {
+                name,
+                sql_type,
+                description: param.description,
+                default,
+            });
+        }
+
+        Ok(Self {
+            title: config.title.unwrap_or_else(|| config.name.clone()),
+            name: config.name,
+            description: config.description,
+            database: config.database,
+            sql: config.sql,
+            params,
+        })
+    }
+
+    /// The declared parameters plus the `format` every tool takes.
+    fn input_schema(&self) -> Value {
+        let mut properties = Map::new();
+        let mut required = Vec::new();
+        for param in &self.params {
+            let mut schema = json!({ "type": json_type(&param.sql_type) });
+            let description = match &param.description {
+                Some(d) => format!("{} ({})", d, param.sql_type),
+                None => param.sql_type.clone(),
+            };
+            schema["description"] = Value::from(description);
+            match &param.default {
+                Some(default) => schema["default"] = default.clone(),
+                None => required.push(param.name.clone()),
+            }
+            properties.insert(param.name.clone(), schema);
+        }
+        properties.insert(
+            "format".to_string(),
+            input_schema::<FormatArgs>()["properties"]["format"].clone(),
+        );
+        json!({
+            "type": "object",
+            "properties": properties,
+            "required": required,
+            "additionalProperties": false
+        })
+    }
+
+    /// The batch running the template: `sp_executesql` with each parameter
+    /// declared by its SQL type and bound from `@P1..@Pn` in order.
+    fn batch(&self) -> String {
+        let mut exec = format!("EXEC sp_executesql N'{}'", self.sql.replace('\'', "''"));
+        if !self.params.is_empty() {
+            let decls: Vec<String> = self
+                .params
+                .iter()
+                .map(|p| format!("@{} {}", p.name, p.sql_type))
+                .collect();
+            exec.push_str(&format!(", N'{}'", decls.join(", ")));
+            for (i, param) in self.params.iter().enumerate() {
+                exec.push_str(&format!(", @{} = @P{}", param.name, i + 1));
+            }
+        }
+        exec
+    }
+
+    fn bind(&self, arguments: &Value) -> Result<Vec<SqlParam>, String> {
+        let empty = Map::new();
+        let arguments = match arguments {
+            Value::Object(map) => map,
+            Value::Null => &empty,
+            _ => return Err("Invalid arguments: expected an object".to_string()),
+        };
+        if let Some(unknown) = arguments
+            .keys()
+            .find(|k| *k != "format" && !self.params.iter().any(|p| &p.name == *k))
+        {
+            return Err(format!("Invalid argument `{}`: unknown parameter", unknown));
+        }
+
+        self.params
+            .iter()
+            .map(|param| {
+                let value = match (arguments.get(&param.name), &param.default) {
+                    (Some(value), _) | (None, Some(value)) => value,
+                    (None, None) => {
+                        return Err(format!("Invalid arguments: missing field `{}`", param.name))
+                    }
+                };
+                parse_typed(value, &param.sql_type)
+                    .map_err(|e| format!("Invalid argument `{}`: {}", param.name, e))
+            })
+            .collect()
+    }
+}
+
+#[async_trait]
+impl DynTool for TemplateTool {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn definition(&self) -> Value {
+        // `read_only = true` is only checked by the lexer when the file is
+        // loaded; nothing at runtime stops the template from writing, so
+        // clients should ask before running any template tool.
+        json!({
+            "name": self.name,
+            "description": self.description,
+            "inputSchema": self.input_schema(),
+            "outputSchema": result_sets_schema(),
+            "annotations": LEXER_CHECKED.to_json(&self.title)
+        })
+    }
+
+    async fn invoke(&self, arguments: &Value, conn: &mut Connection) -> Result<ToolOutput, String> {
+        let params = self.bind(arguments)?;
+        // The other arguments are template parameters, checked by `bind`.
+        let format = match arguments.get("format") {
+            Some(format) => json!({ "format": format }),
+            None => json!({}),
+        };
+        let FormatArgs { format } = parse_args(&format)?;
+        let params: Vec<&dyn claw::IntoSql> = params.iter().map(|p| p.as_sql()).collect();
+        conn.set_database(self.database.as_deref());
+        query_to_output(conn, &self.batch(), &params, None, format).await
+    }
+}
+
+fn is_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// A type name with an optional `(n)`, `(max)` or `(p, s)` suffix.
+fn is_type_name(ty: &str) -> bool {
+    let (base, suffix) = match ty.split_once('(') {
+        Some((base, rest)) => match rest.strip_suffix(')') {
+            Some(args) => (base.trim(), Some(args)),
+            None => return false,
+        },
+        None => (ty, None),
+    };
+    let args_ok = suffix.is_none_or(|args| {
+        let parts: Vec<&str> = args.split(',').map(str::trim).collect();
+        parts == ["max"]
+            || (parts.len() <= 2
+                && parts
+                    .iter()
+                    .all(|p| !p.is_empty() && p.chars().all(|c| c.is_ascii_digit())))
+    });
+    !base.is_empty() && base.chars().all(|c| c.is_ascii_alphanumeric()) && args_ok
+}
+
+/// JSON Schema type clients should send for a SQL type.
+fn json_type(sql_type: &str) -> &'static str {
+    match sql_type.split('(').next().unwrap_or_default().trim() {
+        "tinyint" | "smallint" | "int" | "bigint" => "integer",
+        "real" | "float" | "decimal" | "numeric" | "money" | "smallmoney" => "number",
+        "bit" => "boolean",
+        _ => "string",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tool(toml: &str) -> Result<TemplateTool, String> {
+        let file: ToolsFile = toml::from_str(toml).map_err(|e| e.to_string())?;
+        let mut tools = file
+            .tool
+            .into_iter()
+            .map(TemplateTool::new)
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(tools.remove(0))
+    }
+
+    fn error(toml: &str) -> String {
+        match tool(toml) {
+            Ok(_) => panic!("accepted: {}", toml),
+            Err(e) => e,
+        }
+    }
+
+    fn bind_error(tool: &TemplateTool, arguments: Value) -> String {
+        match tool.bind(&arguments) {
+            Ok(_) => panic!("bound: {}", arguments),
+            Err(e) => e,
+        }
+    }
+
+    const ORDERS: &str = r#"
+        [[tool]]
+        name = "orders"
+        description = "Orders of a customer"
+        sql = "SELECT * FROM dbo.Orders WHERE CustomerId = @customer AND Status = @status"
+        params = [
+            { name = "@customer", type = "INT" },
+            { name = "status", type = "nvarchar(20)", default = "open" },
+        ]
+    "#;
+
+    #[test]
+    fn tools_file_is_loaded_and_validated() {
+        let orders = tool(ORDERS).unwrap();
+        assert_eq!(orders.title, "orders");
+        assert_eq!(orders.params[0].name, "customer");
+        assert_eq!(orders.params[0].sql_type, "int");
+        assert_eq!(orders.definition()["annotations"]["readOnlyHint"], false);
+        let schema = orders.input_schema();
+        assert_eq!(schema["required"], json!(["customer"]));
+        assert_eq!(schema["properties"]["status"]["default"], "open");
+        assert!(schema["properties"]["format"].is_object());
+
+        let base = "[[tool]]\ndescription = \"d\"\n";
+        assert!(
+            error(&format!("{}name = \"a-b\"\nsql = \"SELECT 1\"", base)).contains("name must be")
+        );
+        assert!(
+            error(&format!("{}name = \"t\"\nsql = \"DELETE FROM t\"", base))
+                .contains("set read_only = false")
+        );
+        assert!(tool(&format!(
+            "{}name = \"t\"\nread_only = false\nsql = \"DELETE FROM t\"",
+            base
+        ))
+        .is_ok());
+        assert!(error(&format!(
+            "{}name = \"t\"\nsql = \"SELECT 1\"\nextra = 1",
+            base
+        ))
+        .contains("unknown field"));
+        let with = |params: &str| {
+            format!(
+                "{}name = \"t\"\nsql = \"SELECT 1\"\nparams = [{}]",
+                base, params
+            )
+        };
+        assert_eq!(
+            error(&with(r#"{ name = "format", type = "int" }"#)),
+            "parameter name 'format' is reserved for the output format"
+        );
+        assert_eq!(
+            error(&with(
+                r#"{ name = "a", type = "int" }, { name = "@a", type = "bit" }"#
+            )),
+            "duplicate parameter 'a'"
+        );
+        assert_eq!(
+            error(&with(r#"{ name = "a b", type = "int" }"#)),
+            "invalid parameter name 'a b'"
+        );
+        assert_eq!(
+            error(&with(r#"{ name = "a", type = "int; DROP TABLE t" }"#)),
+            "parameter 'a': invalid type 'int; drop table t'"
+        );
+        assert!(
+            error(&with(r#"{ name = "a", type = "int", default = "x" }"#))
+                .starts_with("parameter 'a': ")
+        );
+    }
+
+    #[test]
+    fn type_names_allow_only_plain_suffixes() {
+        for ty in [
+            "int",
+            "nvarchar(50)",
+            "varchar(max)",
+            "decimal(10, 2)",
+            "datetime2",
+        ] {
+            assert!(is_type_name(ty), "{}", ty);
+        }
+        for ty in [
+            "",
+            "(10)",
+            "nvarchar(",
+            "nvarchar(50",
+            "nvarchar()",
+            "decimal(10,)",
+            "decimal(1, 2, 3)",
+            "nvarchar(-1)",
+            "int)",
+            "int; drop table t",
+            "nvarchar(50) not null",
+        ] {
+            assert!(!is_type_name(ty), "{}", ty);
+        }
+    }
+
+    #[test]
+    fn arguments_are_bound_with_defaults() {
+        let orders = tool(ORDERS).unwrap();
+        let params = orders.bind(&json!({ "customer": 7 })).unwrap();
+        assert!(matches!(params[0], SqlParam::Int(Some(7))));
+        assert!(matches!(&params[1], SqlParam::Text(Some(s)) if s == "open"));
+        let params = orders
+            .bind(&json!({ "customer": "8", "status": "shipped", "format": "json" }))
+            .unwrap();
+        assert!(matches!(params[0], SqlParam::Int(Some(8))));
+        assert!(matches!(&params[1], SqlParam::Text(Some(s)) if s == "shipped"));
+
+        assert_eq!(
+            bind_error(&orders, json!({})),
+            "Invalid arguments: missing field `customer`"
+        );
+        assert_eq!(
+            bind_error(&orders, json!({ "customer": 1, "state": "x" })),
+            "Invalid argument `state`: unknown parameter"
+        );
+        assert_eq!(
+            bind_error(&orders, json!([1])),
+            "Invalid arguments: expected an object"
+        );
+        assert!(bind_error(&orders, json!({ "customer": "x" }))
+            .starts_with("Invalid argument `customer`: "));
+    }
+
+    #[test]
+    fn batch_quotes_the_template() {
+        let orders = tool(ORDERS).unwrap();
+        assert_eq!(
+            orders.batch(),
+            "EXEC sp_executesql N'SELECT * FROM dbo.Orders WHERE CustomerId = @customer AND Status = @status', \
+             N'@customer int, @status nvarchar(20)', @customer = @P1, @status = @P2"
+        );
+        let quoted =
+            tool("[[tool]]\nname = \"q\"\ndescription = \"d\"\nsql = \"SELECT 'it''s', N'x'\"")
+                .unwrap();
+        assert_eq!(
+            quoted.batch(),
+            "EXEC sp_executesql N'SELECT ''it''''s'', N''x'''"
+        );
+    }
+}
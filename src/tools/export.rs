@@ -0,0 +1,1220 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use arrow_array::builder::{
+    BinaryBuilder, BooleanBuilder, Date32Builder, Decimal128Builder, Float32Builder,
+    Float64Builder, Int16Builder, Int32Builder, Int64Builder, StringBuilder,
+    Time64NanosecondBuilder, TimestampMicrosecondBuilder, UInt8Builder,
+};
+use arrow_array::{ArrayRef, RecordBatch};
+use arrow_ipc::writer::FileWriter;
+use arrow_schema::{DataType, Field, Schema, SchemaRef, TimeUnit};
+use async_trait::async_trait;
+use claw::time::DateTime2;
+use claw::{Column, ColumnData, ColumnType, QueryItem, Row};
+use futures_util::TryStreamExt;
+use parquet::arrow::ArrowWriter;
+use parquet::basic::Compression;
+use parquet::file::properties::WriterProperties;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+use tracing::{info, warn};
+
+use crate::connection::Connection;
+use crate::tools::params::parse_params;
+use crate::tools::query::{
+    call_timeout, enforced_batch, ensure_read_only, is_probe, modified_data,
+    rollback_open_transaction,
+};
+use crate::tools::render::{column_keys, csv_field, Cell, Format, Results, Table, TableColumn};
+use crate::tools::schema::with_deadline;
+use crate::tools::value::{cell_to_json, render_cell, type_name};
+use crate::tools::{export_output_schema, Tool, ToolAnnotations, ToolOutput};
+
+/// `export_query` only reads the database, but it creates files.
+const EXPORTS: ToolAnnotations = ToolAnnotations {
+    read_only: false,
+    destructive: false,
+    idempotent: false,
+    open_world: false,
+};
+
+/// Rows buffered per Arrow record batch (and Parquet row group chunk).
+const BATCH_ROWS: usize = 8192;
+/// Rows queued between the query stream and the file writer thread.
+const QUEUED_ROWS: usize = 1024;
+
+/// The `export_query` tool, writing into `--export-dir`.
+pub struct ExportQuery {
+    dir: PathBuf,
+}
+
+impl ExportQuery {
+    pub fn new(dir: &str) -> Result<Self, String> {
+        let dir =
+            std::fs::canonicalize(dir).map_err(|e| format!("Export directory {}: {}", dir, e))?;
+        if !dir.is_dir() {
+            return Err(format!(
+                "Export directory {} is not a directory",
+                dir.display()
+            ));
+        }
+        Ok(Self { dir })
+    }
+}
+
+/// File format of an export: csv, jsonl, parquet, or arrow (Arrow IPC file)
+#[derive(Clone, Copy, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum FileFormat {
+    Csv,
+    Jsonl,
+    Parquet,
+    Arrow,
+}
+
+impl FileFormat {
+    fn extension(self) -> &'static str {
+        match self {
+            FileFormat::Csv => "csv",
+            FileFormat::Jsonl => "jsonl",
+            FileFormat::Parquet => "parquet",
+            FileFormat::Arrow => "arrow",
+        }
+    }
+
+    fn is_arrow(self) -> bool {
+        matches!(self, FileFormat::Parquet | FileFormat::Arrow)
+    }
+}
+
+#[derive(Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct ExportArgs {
+    /// SQL query whose full result is written to the file. Reference parameters as @P1, @P2, ...
+    sql: String,
+    file_format: FileFormat,
+    /// File name inside the export directory (default: export-<unix millis>). The format's extension is added if missing. Existing files are never overwritten
+    file_name: Option<String>,
+    /// Database to run the query in (default: the server's --database)
+    database: Option<String>,
+    /// Values bound to @P1..@Pn, as for `query`
+    #[serde(default)]
+    params: Vec<Value>,
+    /// Cancel the export after this many seconds and delete the partial file (default: --query-timeout, 0 disables)
+    timeout_seconds: Option<u64>,
+    #[serde(default)]
+    format: Format,
+}
+
+#[async_trait]
+impl Tool for ExportQuery {
+    type Args = ExportArgs;
+
+    const NAME: &'static str = "export_query";
+    const TITLE: &'static str = "Export query results";
+    const DESCRIPTION: &'static str = "Run a read-only SQL query and write its full result to a CSV, JSONL, Parquet or Arrow IPC file in the export directory. Returns the file's path, row count, schema and size instead of the rows.";
+
+    fn annotations(&self) -> ToolAnnotations {
+        EXPORTS
+    }
+
+    fn output_schema() -> Value {
+        export_output_schema()
+    }
+
+    async fn call(&self, args: ExportArgs, conn: &mut Connection) -> Result<ToolOutput, String> {
+        ensure_read_only(&args.sql)?;
+        let params = parse_params(&args.params)?;
+        let params: Vec<&dyn claw::IntoSql> = params.iter().map(|p| p.as_sql()).collect();
+        let path = export_path(&self.dir, args.file_name.as_deref(), args.file_format)?;
+
+        conn.set_database(args.database.as_deref());
+        let timeout = call_timeout(conn, args.timeout_seconds);
+        let (wrapped, layers) = enforced_batch(conn, &args.sql)?;
+
+        let open_path = path.clone();
+        let file = blocking(move || {
+            OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&open_path)
+        })
+        .await?
+        .map_err(|e| format!("Cannot create {}: {}", path.display(), e))?;
+        let out = Output::new(file, conn.args().max_export_bytes);
+        let export = stream_to_file(conn, &wrapped, &params, out, args.file_format, timeout).await;
+        if conn.args().rollback_queries {
+            rollback_open_transaction(conn).await;
+        }
+        let export = match export {
+            Ok(export) => export,
+            Err(e) => {
+                let partial = path.clone();
+                match blocking(move || std::fs::remove_file(partial)).await {
+                    Ok(Err(rm)) => warn!("Cannot remove partial export {}: {}", path.display(), rm),
+                    Err(rm) => warn!("Cannot remove partial export {}: {}", path.display(), rm),
+                    Ok(Ok(())) => {}
+                }
+                return Err(e);
+            }
+        };
+        info!(path = %path.display(), rows = export.rows, bytes = export.bytes, "Exported query result");
+
+        let database = conn.current_database().unwrap_or("(unknown)");
+        Ok(export_output(
+            &export,
+            &path,
+            args.file_format,
+            database,
+            layers,
+            args.format,
+        ))
+    }
+}
+
+/// Where an export goes: a plain file name inside `dir`, so an argument
+/// can never point outside it.
+fn export_path(dir: &Path, file_name: Option<&str>, format: FileFormat) -> Result<PathBuf, String> {
+    let ext = format.extension();
+    let name = match file_name {
+        Some(name) => {
+            let plain = !name.is_empty()
+                && !name.starts_with('.')
+                && name
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'));
+            if !plain {
+                return Err(
+                    "Invalid argument `file_name`: use a plain file name of letters, \
+                     digits, '-', '_' and '.', not starting with '.'"
+                        .to_string(),
+                );
+            }
+            if name.ends_with(&format!(".{}", ext)) {
+                name.to_string()
+            } else {
+                format!("{}.{}", name, ext)
+            }
+        }
+        None => {
+            let millis = std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_millis();
+            format!("export-{}.{}", millis, ext)
+        }
+    };
+    Ok(dir.join(name))
+}
+
+/// A finished export.
+struct Export {
+    columns: Vec<Column>,
+    /// The file's Arrow schema, for Parquet and Arrow exports.
+    schema: Option<SchemaRef>,
+    rows: u64,
+    bytes: u64,
+    elapsed: Duration,
+}
+
+/// Run blocking file IO off the async workers.
+async fn blocking<T: Send + 'static>(f: impl FnOnce() -> T + Send + 'static) -> Result<T, String> {
+    tokio::task::spawn_blocking(f)
+        .await
+        .map_err(|e| format!("Export file task failed: {}", e))
+}
+
+/// Stream the single result set of `sql` into `out` as it arrives. Rows go
+/// through a bounded queue to a writer on a blocking thread, so file IO and
+/// encoding never hold up the async workers.
+///
+/// Fails, cancelling the batch on the server, if it times out, returns a
+/// second result set, the writer fails (e.g. at `--max-export-bytes`) or
+/// (in rollback mode) the batch modified data. The caller deletes the file
+/// on failure.
+async fn stream_to_file(
+    conn: &mut Connection,
+    sql: &str,
+    params: &[&dyn claw::IntoSql],
+    out: Output,
+    format: FileFormat,
+    timeout: Option<Duration>,
+) -> Result<Export, String> {
+    let started = Instant::now();
+    let deadline = timeout.map(|t| started + t);
+    let progress = conn.progress();
+    let rollback = conn.args().rollback_queries;
+    let client = match conn.get_client().await {
+        Ok(c) => c,
+        Err(e) => {
+            warn!("Reconnecting after connection error: {}", e);
+            conn.reconnect().await?
+        }
+    };
+
+    let mut out = Some(out);
+    let mut writer: Option<WriterTask> = None;
+    let mut columns = Vec::new();
+    let mut rows = 0;
+    let mut in_probe = false;
+    let mut modified = false;
+    let mut writer_stopped = false;
+    let mut failure = None;
+    let timed_out = || {
+        format!(
+            "Export timed out after {}s and was cancelled; the partial file was deleted",
+            timeout.unwrap_or_default().as_secs()
+        )
+    };
+
+    match with_deadline(deadline, client.execute(sql, params)).await {
+        Some(result) => {
+            let mut stream = result.map_err(|e| format!("Query error: {}", e))?;
+
+            loop {
+                let item = match with_deadline(deadline, stream.try_next()).await {
+                    Some(item) => item.map_err(|e| format!("Result error: {}", e))?,
+                    None => {
+                        failure = Some(timed_out());
+                        break;
+                    }
+                };
+
+                match item {
+                    Some(QueryItem::Metadata(meta)) => {
+                        // The probe is never exported, even when the batch
+                        // itself returned no result set before it.
+                        if rollback && is_probe(meta.columns()) {
+                            in_probe = true;
+                        } else if let Some(out) = out.take() {
+                            columns = meta.columns().to_vec();
+                            let (tx, rx) = mpsc::channel(QUEUED_ROWS);
+                            let writer_columns = columns.clone();
+                            let handle = tokio::task::spawn_blocking(move || {
+                                write_rows(format, out, writer_columns, rx)
+                            });
+                            writer = Some(WriterTask {
+                                rows: tx,
+                                done: handle,
+                            });
+                        } else {
+                            failure = Some(
+                                "export_query writes a single result set, but the batch \
+                                 returned more than one"
+                                    .to_string(),
+                            );
+                            break;
+                        }
+                    }
+                    Some(QueryItem::Row(row)) => {
+                        if let Some(progress) = &progress {
+                            progress.row(1);
+                        }
+                        if in_probe {
+                            modified = true;
+                        } else if let Some(writer) = &writer {
+                            match with_deadline(deadline, writer.rows.send(row)).await {
+                                Some(Ok(())) => rows += 1,
+                                // The writer failed; its error is taken below.
+                                Some(Err(_)) => {
+                                    writer_stopped = true;
+                                    break;
+                                }
+                                None => {
+                                    failure = Some(timed_out());
+                                    break;
+                                }
+                            }
+                        }
+                    }
+                    None => break,
+                }
+            }
+        }
+        None => failure = Some(timed_out()),
+    }
+
+    // Closing the queue lets the writer finish the file.
+    let written = match writer {
+        Some(WriterTask { rows: tx, done }) => {
+            drop(tx);
+            Some(
+                done.await
+                    .map_err(|e| format!("Export writer failed: {}", e))
+                    .and_then(|result| result),
+            )
+        }
+        None => None,
+    };
+    if let Some(e) = failure {
+        conn.cancel();
+        return Err(e);
+    }
+    let written = match written {
+        Some(Ok(written)) => written,
+        Some(Err(e)) => {
+            if writer_stopped {
+                conn.cancel();
+            }
+            return Err(e);
+        }
+        None => return Err("The batch returned no result set to export".to_string()),
+    };
+    if modified {
+        return Err(modified_data());
+    }
+
+    Ok(Export {
+        columns,
+        schema: written.schema,
+        rows,
+        bytes: written.bytes,
+        elapsed: started.elapsed(),
+    })
+}
+
+/// The thread writing an export file and the queue feeding it.
+struct WriterTask {
+    rows: mpsc::Sender<Row>,
+    done: JoinHandle<Result<Written, String>>,
+}
+
+/// Write the rows queued on `rx` to `out`, until the queue is closed.
+fn write_rows(
+    format: FileFormat,
+    out: Output,
+    columns: Vec<Column>,
+    mut rx: mpsc::Receiver<Row>,
+) -> Result<Written, String> {
+    let mut writer = new_writer(format, out, &columns)?;
+    while let Some(row) = rx.blocking_recv() {
+        writer.write_row(&row)?;
+    }
+    writer.finish()
+}
+
+/// The tool result: the export's path, size and schema, never its rows.
+fn export_output(
+    export: &Export,
+    path: &Path,
+    file_format: FileFormat,
+    database: &str,
+    layers: Vec<&'static str>,
+    format: Format,
+) -> ToolOutput {
+    let arrow = file_format.is_arrow();
+    let arrow_types: Vec<Option<String>> = match &export.schema {
+        Some(schema) => schema
+            .fields()
+            .iter()
+            .map(|f| Some(f.data_type().to_string()))
+            .collect(),
+        None => vec![None; export.columns.len()],
+    };
+    let schema: Vec<Value> = export
+        .columns
+        .iter()
+        .zip(&arrow_types)
+        .map(|(c, arrow_type)| {
+            json!({ "name": c.name(), "type": type_name(c.column_type()), "arrow_type": arrow_type })
+        })
+        .collect();
+
+    let mut table_columns = vec![
+        TableColumn {
+            name: "column".to_string(),
+            sql_type: "nvarchar",
+        },
+        TableColumn {
+            name: "type".to_string(),
+            sql_type: "nvarchar",
+        },
+    ];
+    if arrow {
+        table_columns.push(TableColumn {
+            name: "arrow_type".to_string(),
+            sql_type: "nvarchar",
+        });
+    }
+    let text_cell = |text: String| Cell::new(Some(text.clone()), Value::from(text));
+    let rows = export
+        .columns
+        .iter()
+        .zip(&arrow_types)
+        .map(|(c, arrow_type)| {
+            let mut row = vec![
+                text_cell(c.name().to_string()),
+                text_cell(type_name(c.column_type()).to_string()),
+            ];
+            if let Some(arrow_type) = arrow_type {
+                row.push(text_cell(arrow_type.clone()));
+            }
+            row
+        })
+        .collect();
+    let path = path.display().to_string();
+    let results = Results {
+        tables: vec![Table {
+            title: "Schema".to_string(),
+            columns: table_columns,
+            rows,
+            skipped: 0,
+            truncated: false,
+            cut_cells: Vec::new(),
+            notes: Vec::new(),
+        }],
+        fields: Map::new(),
+        notes: vec![
+            format!(
+                "Exported {} rows ({} bytes) to {} in {:.1}s",
+                export.rows,
+                export.bytes,
+                path,
+                export.elapsed.as_secs_f64()
+            ),
+            format!(
+                "Database: {} · Read-only enforcement: {}",
+                database,
+                layers.join(", ")
+            ),
+        ],
+    };
+
+    ToolOutput {
+        content: format.renderer().render(&results),
+        structured: json!({
+            "path": path,
+            "file_format": file_format.extension(),
+            "rows": export.rows,
+            "bytes": export.bytes,
+            "columns": schema,
+            "database": database,
+            "read_only_enforcement": layers,
+            "elapsed_ms": export.elapsed.as_millis() as u64
+        }),
+    }
+}
+
+/// Writes the rows of one result set to a file as they stream in.
+trait RowWriter: Send {
+    fn write_row(&mut self, row: &Row) -> Result<(), String>;
+
+    /// Write anything still buffered and the file's footer.
+    fn finish(self: Box<Self>) -> Result<Written, String>;
+}
+
+/// A completely written export file.
+struct Written {
+    bytes: u64,
+    /// The Arrow schema, for Parquet and Arrow files.
+    schema: Option<SchemaRef>,
+}
+
+/// The export file. Counts the bytes written and fails a write that would
+/// take the file past its limit.
+struct Output {
+    file: File,
+    written: u64,
+    limit: Option<u64>,
+}
+
+impl Output {
+    /// `limit` is `--max-export-bytes`; 0 disables it.
+    fn new(file: File, limit: u64) -> Self {
+        Self {
+            file,
+            written: 0,
+            limit: Some(limit).filter(|&n| n > 0),
+        }
+    }
+
+    fn written(self) -> Written {
+        Written {
+            bytes: self.written,
+            schema: None,
+        }
+    }
+}
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if let Some(limit) = self.limit {
+            if self.written + buf.len() as u64 > limit {
+                return Err(io::Error::other(format!(
+                    "the file reached --max-export-bytes ({} bytes)",
+                    limit
+                )));
+            }
+        }
+        let n = self.file.write(buf)?;
+        self.written += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+fn new_writer(
+    format: FileFormat,
+    out: Output,
+    columns: &[Column],
+) -> Result<Box<dyn RowWriter>, String> {
+    Ok(match format {
+        FileFormat::Csv => {
+            let mut out = BufWriter::new(out);
+            let header: Vec<String> = columns.iter().map(|c| csv_field(c.name())).collect();
+            writeln!(out, "{}", header.join(",")).map_err(write_error)?;
+            Box::new(CsvWriter { out })
+        }
+        FileFormat::Jsonl => Box::new(JsonlWriter {
+            out: BufWriter::new(out),
+            keys: column_keys(columns.iter().map(|c| c.name()))
+                .into_iter()
+                .map(|key| Value::from(key).to_string())
+                .collect(),
+        }),
+        FileFormat::Parquet | FileFormat::Arrow => Box::new(ArrowFile::new(format, out, columns)),
+    })
+}
+
+fn write_error(e: impl std::fmt::Display) -> String {
+    format!("Export write error: {}", e)
+}
+
+/// CSV with a header row; NULL is an empty field. Cells are written in
+/// full, as `render_cell` shows them.
+struct CsvWriter {
+    out: BufWriter<Output>,
+}
+
+impl RowWriter for CsvWriter {
+    fn write_row(&mut self, row: &Row) -> Result<(), String> {
+        let fields: Vec<String> = row
+            .cells()
+            .map(|(col, data)| csv_field(&render_cell(col.column_type(), data).unwrap_or_default()))
+            .collect();
+        writeln!(self.out, "{}", fields.join(",")).map_err(write_error)
+    }
+
+    fn finish(self: Box<Self>) -> Result<Written, String> {
+        let out = self.out.into_inner().map_err(write_error)?;
+        Ok(out.written())
+    }
+}
+
+/// One JSON object per row, keyed by column name in column order (see
+/// [`column_keys`]), with values typed as in `structuredContent`.
+struct JsonlWriter {
+    out: BufWriter<Output>,
+    /// Column keys, already JSON-encoded.
+    keys: Vec<String>,
+}
+
+impl RowWriter for JsonlWriter {
+    fn write_row(&mut self, row: &Row) -> Result<(), String> {
+        let fields: Vec<String> = self
+            .keys
+            .iter()
+            .zip(row.cells())
+            .map(|(key, (col, data))| format!("{}:{}", key, cell_to_json(col.column_type(), data)))
+            .collect();
+        writeln!(self.out, "{{{}}}", fields.join(",")).map_err(write_error)
+    }
+
+    fn finish(self: Box<Self>) -> Result<Written, String> {
+        let out = self.out.into_inner().map_err(write_error)?;
+        Ok(out.written())
+    }
+}
+
+/// Parquet or Arrow IPC: rows are collected into typed column builders and
+/// written as a record batch every `BATCH_ROWS` rows.
+struct ArrowFile {
+    format: FileFormat,
+    names: Vec<String>,
+    builders: Vec<Builder>,
+    buffered: usize,
+    /// Set with the sink at the first flush. That flush waits until every
+    /// decimal column has a non-NULL value to fix its scale, or the file is
+    /// finished.
+    schema: Option<SchemaRef>,
+    sink: Sink,
+}
+
+enum Sink {
+    /// Not opened yet.
+    Pending(Output),
+    Parquet(ArrowWriter<Output>),
+    Ipc(FileWriter<BufWriter<Output>>),
+    /// Taken by `finish`.
+    Closed,
+}
+
+impl ArrowFile {
+    fn new(format: FileFormat, out: Output, columns: &[Column]) -> Self {
+        Self {
+            format,
+            names: columns.iter().map(|c| c.name().to_string()).collect(),
+            builders: columns
+                .iter()
+                .map(|c| Builder::new(c.column_type()))
+                .collect(),
+            buffered: 0,
+            schema: None,
+            sink: Sink::Pending(out),
+        }
+    }
+
+    fn flush(&mut self) -> Result<(), String> {
+        let arrays = self
+            .builders
+            .iter_mut()
+            .map(Builder::finish)
+            .collect::<Result<Vec<ArrayRef>, String>>()?;
+        self.buffered = 0;
+        let schema = match &self.schema {
+            Some(schema) => schema.clone(),
+            None => self.open(&arrays)?,
+        };
+        let batch = RecordBatch::try_new(schema, arrays).map_err(write_error)?;
+        match &mut self.sink {
+            Sink::Parquet(w) => w.write(&batch).map_err(write_error),
+            Sink::Ipc(w) => w.write(&batch).map_err(write_error),
+            Sink::Pending(_) | Sink::Closed => Ok(()),
+        }
+    }
+
+    /// Count a row appended to the builders, and write a record batch every
+    /// `BATCH_ROWS` rows. Rows stay buffered past that while the schema is
+    /// not fixed and a decimal column has only had NULLs.
+    fn row_added(&mut self) -> Result<(), String> {
+        self.buffered += 1;
+        let scales_known = self.schema.is_some()
+            || self
+                .builders
+                .iter()
+                .all(|b| !matches!(b, Builder::Decimal { scale: None, .. }));
+        if self.buffered >= BATCH_ROWS && scales_known {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Start the file with the schema of the first record batch.
+    fn open(&mut self, arrays: &[ArrayRef]) -> Result<SchemaRef, String> {
+        let fields: Vec<Field> = self
+            .names
+            .iter()
+            .zip(arrays)
+            .map(|(name, array)| Field::new(name, array.data_type().clone(), true))
+            .collect();
+        let schema = Arc::new(Schema::new(fields));
+        let Sink::Pending(out) = std::mem::replace(&mut self.sink, Sink::Closed) else {
+            return Err(write_error("the file was already started"));
+        };
+        self.sink = match self.format {
+            FileFormat::Parquet => {
+                let props = WriterProperties::builder()
+                    .set_compression(Compression::SNAPPY)
+                    .build();
+                Sink::Parquet(
+                    ArrowWriter::try_new(out, schema.clone(), Some(props)).map_err(write_error)?,
+                )
+            }
+            _ => Sink::Ipc(FileWriter::try_new(BufWriter::new(out), &schema).map_err(write_error)?),
+        };
+        self.schema = Some(schema.clone());
+        Ok(schema)
+    }
+}
+
+impl RowWriter for ArrowFile {
+    fn write_row(&mut self, row: &Row) -> Result<(), String> {
+        for ((col, data), builder) in row.cells().zip(&mut self.builders) {
+            builder
+                .append(col.column_type(), data)
+                .map_err(|e| format!("Column {}: {}", col.name(), e))?;
+        }
+        self.row_added()
+    }
+
+    fn finish(mut self: Box<Self>) -> Result<Written, String> {
+        // An empty result still gets a file with its schema.
+        if self.buffered > 0 || self.schema.is_none() {
+            self.flush()?;
+        }
+        let out = match std::mem::replace(&mut self.sink, Sink::Closed) {
+            Sink::Parquet(w) => w.into_inner().map_err(write_error)?,
+            Sink::Ipc(mut w) => {
+                w.finish().map_err(write_error)?;
+                let out = w.into_inner().map_err(write_error)?;
+                out.into_inner().map_err(write_error)?
+            }
+            Sink::Pending(_) | Sink::Closed => return Err(write_error("the file was not started")),
+        };
+        Ok(Written {
+            schema: self.schema.take(),
+            ..out.written()
+        })
+    }
+}
+
+/// Arrow type a SQL Server column is exported as.
+fn arrow_type(column_type: ColumnType) -> DataType {
+    match column_type {
+        ColumnType::Bit | ColumnType::Bitn => DataType::Boolean,
+        ColumnType::Int1 => DataType::UInt8,
+        ColumnType::Int2 => DataType::Int16,
+        ColumnType::Int4 => DataType::Int32,
+        // A nullable `Intn` column does not say its width; use the widest.
+        ColumnType::Int8 | ColumnType::Intn => DataType::Int64,
+        ColumnType::Float4 => DataType::Float32,
+        ColumnType::Float8 | ColumnType::Floatn => DataType::Float64,
+        // A nullable `smallmoney` column arrives as `Money`; both fit.
+        ColumnType::Money | ColumnType::Money4 => DataType::Decimal128(19, MONEY_SCALE),
+        // The scale is only known from the values; see `Builder::Decimal`.
+        ColumnType::Decimaln | ColumnType::Numericn => DataType::Decimal128(DECIMAL_PRECISION, 0),
+        ColumnType::Daten => DataType::Date32,
+        ColumnType::Timen => DataType::Time64(TimeUnit::Nanosecond),
+        ColumnType::Datetime4
+        | ColumnType::Datetime
+        | ColumnType::Datetimen
+        | ColumnType::Datetime2 => DataType::Timestamp(TimeUnit::Microsecond, None),
+        // The wire value is UTC.
+        ColumnType::DatetimeOffsetn => DataType::Timestamp(TimeUnit::Microsecond, Some(UTC.into())),
+        ColumnType::BigVarBin | ColumnType::BigBinary | ColumnType::Image => DataType::Binary,
+        // GUIDs, XML and the rest are their text.
+        _ => DataType::Utf8,
+    }
+}
+
+const UTC: &str = "+00:00";
+
+/// `decimal` and `numeric` are exported at the largest precision, so any
+/// column fits whatever its declared precision.
+const DECIMAL_PRECISION: u8 = 38;
+/// `money` and `smallmoney` have four decimal places.
+const MONEY_SCALE: i8 = 4;
+
+/// Days between 0001-01-01 (the `date` epoch) and 1970-01-01.
+const DAYS_0001_TO_1970: i64 = 719_162;
+/// Days between 1900-01-01 (the `datetime` epoch) and 1970-01-01.
+const DAYS_1900_TO_1970: i64 = 25_567;
+const MICROS_PER_DAY: i64 = 86_400_000_000;
+
+/// A column builder for each Arrow type `arrow_type` produces.
+enum Builder {
+    Boolean(BooleanBuilder),
+    UInt8(UInt8Builder),
+    Int16(Int16Builder),
+    Int32(Int32Builder),
+    Int64(Int64Builder),
+    Float32(Float32Builder),
+    Float64(Float64Builder),
+    /// Decimal values with `precision` digits, `scale` of them decimal
+    /// places. The scale of a `decimal` column is taken from its first
+    /// non-NULL value (SQL Server sends every value of a column at the
+    /// column's scale), or is 0 if the whole column is NULL.
+    Decimal {
+        builder: Decimal128Builder,
+        precision: u8,
+        scale: Option<i8>,
+    },
+    Date32(Date32Builder),
+    Time64(Time64NanosecondBuilder),
+    Timestamp(TimestampMicrosecondBuilder),
+    Binary(BinaryBuilder),
+    Utf8(StringBuilder),
+}
+
+impl Builder {
+    fn new(column_type: ColumnType) -> Self {
+        let data_type = arrow_type(column_type);
+        match &data_type {
+            DataType::Boolean => Builder::Boolean(BooleanBuilder::new()),
+            DataType::UInt8 => Builder::UInt8(UInt8Builder::new()),
+            DataType::Int16 => Builder::Int16(Int16Builder::new()),
+            DataType::Int32 => Builder::Int32(Int32Builder::new()),
+            DataType::Int64 => Builder::Int64(Int64Builder::new()),
+            DataType::Float32 => Builder::Float32(Float32Builder::new()),
+            DataType::Float64 => Builder::Float64(Float64Builder::new()),
+            &DataType::Decimal128(precision, scale) => Builder::Decimal {
+                builder: Decimal128Builder::new(),
+                precision,
+                scale: matches!(column_type, ColumnType::Money | ColumnType::Money4)
+                    .then_some(scale),
+            },
+            DataType::Date32 => Builder::Date32(Date32Builder::new()),
+            DataType::Time64(_) => Builder::Time64(Time64NanosecondBuilder::new()),
+            DataType::Timestamp(_, tz) => {
+                Builder::Timestamp(TimestampMicrosecondBuilder::new().with_timezone_opt(tz.clone()))
+            }
+            DataType::Binary => Builder::Binary(BinaryBuilder::new()),
+            _ => Builder::Utf8(StringBuilder::new()),
+        }
+    }
+
+    fn append(&mut self, column_type: ColumnType, data: &ColumnData<'_>) -> Result<(), String> {
+        match (self, data) {
+            (Builder::Boolean(b), ColumnData::Bit(v)) => b.append_option(*v),
+            (Builder::UInt8(b), ColumnData::U8(v)) => b.append_option(*v),
+            (Builder::Int16(b), ColumnData::I16(v)) => b.append_option(*v),
+            (Builder::Int32(b), ColumnData::I32(v)) => b.append_option(*v),
+            (Builder::Int64(b), ColumnData::U8(v)) => b.append_option(v.map(i64::from)),
+            (Builder::Int64(b), ColumnData::I16(v)) => b.append_option(v.map(i64::from)),
+            (Builder::Int64(b), ColumnData::I32(v)) => b.append_option(v.map(i64::from)),
+            (Builder::Int64(b), ColumnData::I64(v)) => b.append_option(*v),
+            (Builder::Float32(b), ColumnData::F32(v)) => b.append_option(*v),
+            (Builder::Float64(b), ColumnData::F32(v)) => b.append_option(v.map(f64::from)),
+            (Builder::Float64(b), ColumnData::F64(v)) => b.append_option(*v),
+            (Builder::Decimal { builder, scale, .. }, ColumnData::Numeric(Some(n))) => {
+                let scale = *scale.get_or_insert(n.scale() as i8);
+                builder.append_value(rescale(n.value(), n.scale() as i8, scale)?)
+            }
+            // Money arrives as a float of its ten-thousandths.
+            (Builder::Decimal { builder, .. }, ColumnData::F64(v)) => {
+                builder.append_option(v.map(|v| (v * 10_000.0).round() as i128))
+            }
+            (Builder::Date32(b), ColumnData::Date(v)) => {
+                b.append_option(v.map(|d| (d.days() as i64 - DAYS_0001_TO_1970) as i32))
+            }
+            (Builder::Time64(b), ColumnData::Time(v)) => {
+                b.append_option(v.map(|t| t.increments() as i64 * 10i64.pow(9 - t.scale() as u32)))
+            }
+            (Builder::Timestamp(b), ColumnData::DateTime(v)) => b.append_option(v.map(|dt| {
+                // 1/300 second ticks.
+                (dt.days() as i64 - DAYS_1900_TO_1970) * MICROS_PER_DAY
+                    + dt.seconds_fragments() as i64 * 10_000 / 3
+            })),
+            (Builder::Timestamp(b), ColumnData::SmallDateTime(v)) => {
+                b.append_option(v.map(|dt| {
+                    // Minutes since midnight.
+                    (dt.days() as i64 - DAYS_1900_TO_1970) * MICROS_PER_DAY
+                        + dt.seconds_fragments() as i64 * 60_000_000
+                }))
+            }
+            (Builder::Timestamp(b), ColumnData::DateTime2(v)) => {
+                b.append_option(v.map(datetime2_micros))
+            }
+            (Builder::Timestamp(b), ColumnData::DateTimeOffset(v)) => {
+                b.append_option(v.map(|dto| datetime2_micros(dto.datetime2())))
+            }
+            (Builder::Binary(b), ColumnData::Binary(v)) => b.append_option(v.as_deref()),
+            (Builder::Utf8(b), data) => b.append_option(render_cell(column_type, data)),
+            (builder, data) => {
+                if !is_null(data) {
+                    return Err(format!(
+                        "unexpected {} value in a {} column",
+                        variant_name(data),
+                        type_name(column_type)
+                    ));
+                }
+                builder.append_null();
+            }
+        }
+        Ok(())
+    }
+
+    fn append_null(&mut self) {
+        match self {
+            Builder::Boolean(b) => b.append_null(),
+            Builder::UInt8(b) => b.append_null(),
+            Builder::Int16(b) => b.append_null(),
+            Builder::Int32(b) => b.append_null(),
+            Builder::Int64(b) => b.append_null(),
+            Builder::Float32(b) => b.append_null(),
+            Builder::Float64(b) => b.append_null(),
+            Builder::Decimal { builder, .. } => builder.append_null(),
+            Builder::Date32(b) => b.append_null(),
+            Builder::Time64(b) => b.append_null(),
+            Builder::Timestamp(b) => b.append_null(),
+            Builder::Binary(b) => b.append_null(),
+            Builder::Utf8(b) => b.append_null(),
+        }
+    }
+
+    /// The values appended since the last call, as one array.
+    fn finish(&mut self) -> Result<ArrayRef, String> {
+        Ok(match self {
+            Builder::Boolean(b) => Arc::new(b.finish()),
+            Builder::UInt8(b) => Arc::new(b.finish()),
+            Builder::Int16(b) => Arc::new(b.finish()),
+            Builder::Int32(b) => Arc::new(b.finish()),
+            Builder::Int64(b) => Arc::new(b.finish()),
+            Builder::Float32(b) => Arc::new(b.finish()),
+            Builder::Float64(b) => Arc::new(b.finish()),
+            Builder::Decimal {
+                builder,
+                precision,
+                scale,
+            } => {
+                // Later batches must match the schema the first one set.
+                let scale = *scale.get_or_insert(0);
+                Arc::new(
+                    builder
+                        .finish()
+                        .with_precision_and_scale(*precision, scale)
+                        .map_err(write_error)?,
+                )
+            }
+            Builder::Date32(b) => Arc::new(b.finish()),
+            Builder::Time64(b) => Arc::new(b.finish()),
+            Builder::Timestamp(b) => Arc::new(b.finish()),
+            Builder::Binary(b) => Arc::new(b.finish()),
+            Builder::Utf8(b) => Arc::new(b.finish()),
+        })
+    }
+}
+
+/// A decimal's unscaled `value` at scale `from`, brought to scale `to`.
+fn rescale(value: i128, from: i8, to: i8) -> Result<i128, String> {
+    if from > to {
+        return Err(format!(
+            "a value with {} decimal places in a column exported with {}",
+            from, to
+        ));
+    }
+    10i128
+        .checked_pow((to - from) as u32)
+        .and_then(|factor| value.checked_mul(factor))
+        .ok_or_else(|| format!("a value too large for {} decimal places", to))
+}
+
+fn datetime2_micros(dt: DateTime2) -> i64 {
+    let time = dt.time();
+    let micros = time.increments() as i64 * 1_000_000 / 10i64.pow(time.scale() as u32);
+    (dt.date().days() as i64 - DAYS_0001_TO_1970) * MICROS_PER_DAY + micros
+}
+
+fn is_null(data: &ColumnData<'_>) -> bool {
+    matches!(
+        data,
+        ColumnData::U8(None)
+            | ColumnData::I16(None)
+            | ColumnData::I32(None)
+            | ColumnData::I64(None)
+            | ColumnData::F32(None)
+            | ColumnData::F64(None)
+            | ColumnData::Bit(None)
+            | ColumnData::String(None)
+            | ColumnData::Guid(None)
+            | ColumnData::Binary(None)
+            | ColumnData::Numeric(None)
+            | ColumnData::Xml(None)
+            | ColumnData::DateTime(None)
+            | ColumnData::SmallDateTime(None)
+            | ColumnData::Time(None)
+            | ColumnData::Date(None)
+            | ColumnData::DateTime2(None)
+            | ColumnData::DateTimeOffset(None)
+    )
+}
+
+fn variant_name(data: &ColumnData<'_>) -> &'static str {
+    match data {
+        ColumnData::U8(_) => "tinyint",
+        ColumnData::I16(_) => "smallint",
+        ColumnData::I32(_) => "int",
+        ColumnData::I64(_) => "bigint",
+        ColumnData::F32(_) => "real",
+        ColumnData::F64(_) => "float",
+        ColumnData::Bit(_) => "bit",
+        ColumnData::String(_) => "string",
+        ColumnData::Guid(_) => "uniqueidentifier",
+        ColumnData::Binary(_) => "binary",
+        ColumnData::Numeric(_) => "numeric",
+        ColumnData::Xml(_) => "xml",
+        ColumnData::DateTime(_) => "datetime",
+        ColumnData::SmallDateTime(_) => "smalldatetime",
+        ColumnData::Time(_) => "time",
+        ColumnData::Date(_) => "date",
+        ColumnData::DateTime2(_) => "datetime2",
+        ColumnData::DateTimeOffset(_) => "datetimeoffset",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arrow_array::cast::AsArray;
+    use arrow_array::types::{
+        Date32Type, Decimal128Type, Time64NanosecondType, TimestampMicrosecondType,
+    };
+    use arrow_array::Array;
+    use claw::numeric::Numeric;
+    use claw::time::{Date, DateTime, SmallDateTime, Time};
+
+    fn build(column_type: ColumnType, values: &[ColumnData<'static>]) -> ArrayRef {
+        let mut builder = Builder::new(column_type);
+        for value in values {
+            builder.append(column_type, value).unwrap();
+        }
+        builder.finish().unwrap()
+    }
+
+    #[test]
+    fn dates_count_days_from_1970() {
+        let array = build(
+            ColumnType::Daten,
+            &[
+                ColumnData::Date(Some(Date::new(DAYS_0001_TO_1970 as u32))),
+                ColumnData::Date(Some(Date::new(DAYS_0001_TO_1970 as u32 - 1))),
+                ColumnData::Date(None),
+            ],
+        );
+        let dates = array.as_primitive::<Date32Type>();
+        assert_eq!(dates.value(0), 0);
+        assert_eq!(dates.value(1), -1);
+        assert!(dates.is_null(2));
+    }
+
+    #[test]
+    fn times_are_nanoseconds_whatever_their_scale() {
+        let array = build(
+            ColumnType::Timen,
+            &[
+                ColumnData::Time(Some(Time::new(123, 3))),
+                ColumnData::Time(Some(Time::new(1_234_567, 7))),
+            ],
+        );
+        let times = array.as_primitive::<Time64NanosecondType>();
+        assert_eq!(times.value(0), 123_000_000);
+        assert_eq!(times.value(1), 123_456_700);
+    }
+
+    #[test]
+    fn datetimes_are_microseconds_from_1970() {
+        let noon = DateTime2::new(
+            Date::new(DAYS_0001_TO_1970 as u32 + 1),
+            Time::new(12 * 3600 * 10_000_000 + 5, 7),
+        );
+        assert_eq!(datetime2_micros(noon), MICROS_PER_DAY + 12 * 3_600_000_000);
+
+        let array = build(
+            ColumnType::Datetimen,
+            &[
+                // 1970-01-01 00:00:01, in 1/300 second ticks.
+                ColumnData::DateTime(Some(DateTime::new(DAYS_1900_TO_1970 as i32, 300))),
+                // 1970-01-02 00:01, in minutes.
+                ColumnData::SmallDateTime(Some(SmallDateTime::new(
+                    DAYS_1900_TO_1970 as u16 + 1,
+                    1,
+                ))),
+            ],
+        );
+        let stamps = array.as_primitive::<TimestampMicrosecondType>();
+        assert_eq!(stamps.value(0), 1_000_000);
+        assert_eq!(stamps.value(1), MICROS_PER_DAY + 60_000_000);
+    }
+
+    #[test]
+    fn decimals_keep_their_digits() {
+        let array = build(
+            ColumnType::Numericn,
+            &[
+                ColumnData::Numeric(None),
+                ColumnData::Numeric(Some(Numeric::new_with_scale(-12_345, 2))),
+                ColumnData::Numeric(Some(Numeric::new_with_scale(7, 2))),
+            ],
+        );
+        assert_eq!(array.data_type(), &DataType::Decimal128(38, 2));
+        let decimals = array.as_primitive::<Decimal128Type>();
+        assert!(decimals.is_null(0));
+        assert_eq!(decimals.value(1), -12_345);
+        assert_eq!(decimals.value(2), 7);
+    }
+
+    #[test]
+    fn money_has_four_decimal_places() {
+        let array = build(
+            ColumnType::Money,
+            &[
+                ColumnData::F64(Some(12.3456)),
+                ColumnData::F64(Some(-0.0001)),
+            ],
+        );
+        assert_eq!(array.data_type(), &DataType::Decimal128(19, 4));
+        let money = array.as_primitive::<Decimal128Type>();
+        assert_eq!(money.value(0), 123_456);
+        assert_eq!(money.value(1), -1);
+    }
+
+    #[test]
+    fn decimal_scale_waits_for_a_value() {
+        let path = std::env::temp_dir().join(format!("prowl-test-{}.parquet", std::process::id()));
+        let out = Output::new(File::create(&path).unwrap(), 0);
+        let columns = [Column::new("amount".to_string(), ColumnType::Numericn)];
+        let mut file = ArrowFile::new(FileFormat::Parquet, out, &columns);
+        fn append(file: &mut ArrowFile, value: Option<Numeric>) {
+            file.builders[0]
+                .append(ColumnType::Numericn, &ColumnData::Numeric(value))
+                .unwrap();
+            file.row_added().unwrap();
+        }
+        for _ in 0..BATCH_ROWS {
+            append(&mut file, None);
+        }
+        assert!(file.schema.is_none());
+        append(&mut file, Some(Numeric::new_with_scale(125, 2)));
+        assert!(file.schema.is_some());
+        append(&mut file, Some(Numeric::new_with_scale(5, 2)));
+        let written = Box::new(file).finish().unwrap();
+        assert_eq!(
+            written.schema.unwrap().field(0).data_type(),
+            &DataType::Decimal128(38, 2)
+        );
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn decimals_are_rescaled_up_only() {
+        assert_eq!(rescale(12, 0, 2), Ok(1200));
+        assert_eq!(rescale(12, 2, 2), Ok(12));
+        assert!(rescale(1234, 2, 0).is_err());
+        assert!(rescale(i128::MAX, 0, 1).is_err());
+    }
+
+    #[test]
+    fn writes_stop_at_the_byte_limit() {
+        let path = std::env::temp_dir().join(format!("prowl-test-{}.csv", std::process::id()));
+        let file = File::create(&path).unwrap();
+        let mut out = Output::new(file, 10);
+        out.write_all(b"0123456789").unwrap();
+        let err = out.write_all(b"x").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "the file reached --max-export-bytes (10 bytes)"
+        );
+        assert_eq!(out.written().bytes, 10);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn an_empty_result_still_gets_a_schema() {
+        let path = std::env::temp_dir().join(format!("prowl-test-{}.arrow", std::process::id()));
+        let out = Output::new(File::create(&path).unwrap(), 0);
+        let columns = [
+            Column::new("id".to_string(), ColumnType::Int4),
+            Column::new("price".to_string(), ColumnType::Money),
+        ];
+        let written = new_writer(FileFormat::Arrow, out, &columns)
+            .unwrap()
+            .finish()
+            .unwrap();
+        let schema = written.schema.unwrap();
+        assert_eq!(schema.field(0).data_type(), &DataType::Int32);
+        assert_eq!(schema.field(1).data_type(), &DataType::Decimal128(19, 4));
+        assert_eq!(written.bytes, std::fs::metadata(&path).unwrap().len());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
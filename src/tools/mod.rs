@@ -1,174 +1,443 @@
+pub mod custom;
+pub mod export;
 pub mod monitor;
+pub mod params;
 pub mod query;
+pub mod render;
 pub mod schema;
+pub mod tsql;
+pub mod value;
 
+use async_trait::async_trait;
+use schemars::generate::SchemaSettings;
+use schemars::JsonSchema;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
 use serde_json::{json, Value};
 
 use crate::connection::Connection;
+use crate::tools::render::Format;
+use crate::Args;
 
-pub fn tool_definitions() -> Value {
-    json!([
-        {
-            "name": "list_databases",
-            "description": "List all databases on the SQL Server instance",
-            "inputSchema": {
-                "type": "object",
-                "properties": {},
-                "required": []
-            }
-        },
-        {
-            "name": "list_tables",
-            "description": "List all tables in a database",
-            "inputSchema": {
-                "type": "object",
-                "properties": {
-                    "database": {
-                        "type": "string",
-                        "description": "Database name"
-                    }
-                },
-                "required": ["database"]
-            }
-        },
-        {
-            "name": "describe_table",
-            "description": "Describe a table's columns, types, nullability, primary keys, and foreign keys",
-            "inputSchema": {
-                "type": "object",
-                "properties": {
-                    "database": { "type": "string", "description": "Database name" },
-                    "schema": { "type": "string", "description": "Schema name (default: dbo)" },
-                    "table": { "type": "string", "description": "Table name" }
-                },
-                "required": ["database", "table"]
-            }
-        },
-        {
-            "name": "query",
-            "description": "Execute a read-only SQL query and return results as a markdown table. Write operations are blocked.",
-            "inputSchema": {
-                "type": "object",
-                "properties": {
-                    "sql": { "type": "string", "description": "SQL query to execute" },
-                    "max_rows": { "type": "integer", "description": "Maximum rows to return (default: 100)" }
-                },
-                "required": ["sql"]
-            }
-        },
-        {
-            "name": "query_plan",
-            "description": "Show the execution plan for a SQL query",
-            "inputSchema": {
-                "type": "object",
-                "properties": {
-                    "sql": { "type": "string", "description": "SQL query to get execution plan for" }
-                },
-                "required": ["sql"]
-            }
-        },
-        {
-            "name": "active_sessions",
-            "description": "Show active user sessions on the SQL Server",
-            "inputSchema": {
-                "type": "object",
-                "properties": {},
-                "required": []
-            }
-        },
-        {
-            "name": "blocking_chains",
-            "description": "Show blocking chains — sessions blocking other sessions",
-            "inputSchema": {
-                "type": "object",
-                "properties": {},
-                "required": []
-            }
-        },
-        {
-            "name": "index_usage",
-            "description": "Show top unused and missing indexes",
-            "inputSchema": {
-                "type": "object",
-                "properties": {
-                    "database": { "type": "string", "description": "Database name (optional)" }
-                },
-                "required": []
-            }
+/// A tool's result: text in the requested format (markdown by default) for
+/// display and for clients without structured output support, plus the
+/// same data as `structuredContent` matching the tool's `outputSchema`.
+pub struct ToolOutput {
+    /// Text content blocks; see [`render::Renderer`].
+    pub content: Vec<String>,
+    pub structured: Value,
+}
+
+/// Description of a result column's `type`; see `value::type_name`.
+const TYPE_DESCRIPTION: &str = "SQL Server type name. Nullable integer, float, datetime and money columns are intn, floatn, datetimen and moneyn, since their size is not reported";
+
+/// `outputSchema` of the tools that return result sets.
+fn result_sets_schema() -> Value {
+    json!({
+        "type": "object",
+        "properties": {
+            "result_sets": {
+                "type": "array",
+                "items": {
+                    "type": "object",
+                    "properties": {
+                        "columns": {
+                            "type": "array",
+                            "items": {
+                                "type": "object",
+                                "properties": {
+                                    "name": { "type": "string" },
+                                    "type": { "type": "string", "description": TYPE_DESCRIPTION }
+                                },
+                                "required": ["name", "type"]
+                            }
+                        },
+                        "rows": {
+                            "type": "array",
+                            "description": "Rows as arrays of values in column order. Integers, floats and bits are JSON numbers and booleans; other types are strings; NULL is null",
+                            "items": { "type": "array" }
+                        },
+                        "truncated": { "type": "boolean", "description": "A limit cut this result set short. Unless `query` was called with `read_to_end`, the rest of the batch was cancelled there, so later result sets are missing" },
+                        "cut_cells": {
+                            "type": "array",
+                            "description": "Columns with cells cut at max_cell_chars (or binary values summarized), and how many",
+                            "items": {
+                                "type": "object",
+                                "properties": {
+                                    "column": { "type": "string" },
+                                    "cells": { "type": "integer" }
+                                },
+                                "required": ["column", "cells"]
+                            }
+                        }
+                    },
+                    "required": ["columns", "rows", "truncated", "cut_cells"]
+                }
+            },
+            "timed_out": { "type": "boolean", "description": "The batch was cancelled by its timeout; results are partial" },
+            "stopped_by": { "type": ["string", "null"], "enum": ["max_rows", "max_bytes", null], "description": "The first limit that cut a result set short, if any. Unless `query` was called with `read_to_end`, the batch was cancelled at that point" },
+            "max_cell_chars": { "type": ["integer", "null"], "description": "Characters kept per cell; null if cells are not cut" },
+            "elapsed_ms": { "type": "integer" }
         },
-        {
-            "name": "table_sizes",
-            "description": "Show space used per table in a database",
-            "inputSchema": {
-                "type": "object",
-                "properties": {
-                    "database": { "type": "string", "description": "Database name" }
-                },
-                "required": ["database"]
-            }
+        "required": ["result_sets", "timed_out", "stopped_by", "max_cell_chars", "elapsed_ms"]
+    })
+}
+
+/// `result_sets_schema` plus the `query` tool's extra fields.
+fn query_output_schema() -> Value {
+    let mut schema = result_sets_schema();
+    schema["properties"]["database"] =
+        json!({ "type": "string", "description": "Database the query ran in" });
+    schema["properties"]["next_cursor"] = json!({
+        "type": ["string", "null"],
+        "description": "Pass as `cursor` to get the next page; null if nothing was cut off by a limit"
+    });
+    schema["properties"]["read_only_enforcement"] = json!({
+        "type": "array",
+        "items": { "type": "string" },
+        "description": "Read-only enforcement layers that were active"
+    });
+    schema["required"] = json!([
+        "result_sets",
+        "timed_out",
+        "stopped_by",
+        "max_cell_chars",
+        "elapsed_ms",
+        "database",
+        "read_only_enforcement",
+        "next_cursor"
+    ]);
+    schema
+}
+
+fn pool_stats_schema() -> Value {
+    let names = [
+        "size",
+        "in_use",
+        "idle",
+        "opened",
+        "reused",
+        "closed_idle",
+        "closed_expired",
+        "closed_unhealthy",
+        "closed_context",
+        "closed_transaction",
+    ];
+    let properties: serde_json::Map<String, Value> = names
+        .iter()
+        .map(|n| (n.to_string(), json!({ "type": "integer" })))
+        .collect();
+    json!({
+        "type": "object",
+        "properties": properties,
+        "required": names
+    })
+}
+
+/// `outputSchema` of `export_query`.
+fn export_output_schema() -> Value {
+    json!({
+        "type": "object",
+        "properties": {
+            "path": { "type": "string", "description": "Absolute path of the written file" },
+            "file_format": { "type": "string" },
+            "rows": { "type": "integer" },
+            "bytes": { "type": "integer", "description": "Size of the file" },
+            "columns": {
+                "type": "array",
+                "items": {
+                    "type": "object",
+                    "properties": {
+                        "name": { "type": "string" },
+                        "type": { "type": "string", "description": TYPE_DESCRIPTION },
+                        "arrow_type": { "type": ["string", "null"], "description": "Arrow type of the column in Parquet and Arrow files" }
+                    },
+                    "required": ["name", "type", "arrow_type"]
+                }
+            },
+            "database": { "type": "string" },
+            "read_only_enforcement": { "type": "array", "items": { "type": "string" } },
+            "elapsed_ms": { "type": "integer" }
         },
-        {
-            "name": "server_info",
-            "description": "Show SQL Server version, edition, and configuration",
-            "inputSchema": {
-                "type": "object",
-                "properties": {},
-                "required": []
-            }
-        }
-    ])
-}
-
-pub async fn dispatch(
-    tool_name: &str,
-    arguments: &Value,
-    conn: &mut Connection,
-) -> Result<String, String> {
-    match tool_name {
-        "list_databases" => schema::list_databases(conn).await,
-        "list_tables" => {
-            let db = get_str(arguments, "database")?;
-            schema::list_tables(conn, &db).await
-        }
-        "describe_table" => {
-            let db = get_str(arguments, "database")?;
-            let schema = arguments
-                .get("schema")
-                .and_then(|v| v.as_str())
-                .unwrap_or("dbo");
-            let table = get_str(arguments, "table")?;
-            schema::describe_table(conn, &db, schema, &table).await
+        "required": ["path", "file_format", "rows", "bytes", "columns", "database", "read_only_enforcement", "elapsed_ms"]
+    })
+}
+
+/// MCP tool annotations: hints clients use to decide which calls need the
+/// user's approval.
+#[derive(Clone, Copy)]
+pub struct ToolAnnotations {
+    /// The tool does not modify its environment.
+    pub read_only: bool,
+    /// Any modification may be destructive (meaningful only if not read-only).
+    pub destructive: bool,
+    /// Repeating a call with the same arguments has no further effect.
+    pub idempotent: bool,
+    /// The tool reaches beyond the configured SQL Server.
+    pub open_world: bool,
+}
+
+impl ToolAnnotations {
+    /// The `annotations` object of a tool definition.
+    pub fn to_json(self, title: &str) -> Value {
+        json!({
+            "title": title,
+            "readOnlyHint": self.read_only,
+            "destructiveHint": self.destructive,
+            "idempotentHint": self.idempotent,
+            "openWorldHint": self.open_world
+        })
+    }
+}
+
+/// The built-in tools other than `export_query` only read: catalog views,
+/// DMVs, prowl's own state, or user data through `query` when a runtime
+/// enforcement layer backs its lexer.
+pub const READ_ONLY: ToolAnnotations = ToolAnnotations {
+    read_only: true,
+    destructive: false,
+    idempotent: true,
+    open_world: false,
+};
+
+/// A tool whose batches are only checked by the T-SQL lexer: it is meant to
+/// read, but nothing at runtime stops a batch that gets past the lexer.
+pub const LEXER_CHECKED: ToolAnnotations = ToolAnnotations {
+    read_only: false,
+    destructive: true,
+    idempotent: false,
+    open_world: false,
+};
+
+/// A built-in tool. Its arguments are a serde struct, from which the
+/// `inputSchema` is generated, so the schema and the code reading the
+/// arguments cannot drift apart.
+#[async_trait]
+pub trait Tool: Send + Sync {
+    type Args: DeserializeOwned + JsonSchema + Send;
+
+    const NAME: &'static str;
+    const TITLE: &'static str;
+    const DESCRIPTION: &'static str;
+
+    fn annotations(&self) -> ToolAnnotations {
+        READ_ONLY
+    }
+
+    fn output_schema() -> Value {
+        result_sets_schema()
+    }
+
+    async fn call(&self, args: Self::Args, conn: &mut Connection) -> Result<ToolOutput, String>;
+}
+
+/// A tool as the server sees it: its `tools/list` entry and a call taking
+/// the raw JSON arguments.
+#[async_trait]
+pub trait DynTool: Send + Sync {
+    fn name(&self) -> &str;
+
+    fn definition(&self) -> Value;
+
+    async fn invoke(&self, arguments: &Value, conn: &mut Connection) -> Result<ToolOutput, String>;
+}
+
+#[async_trait]
+impl<T: Tool> DynTool for T {
+    fn name(&self) -> &str {
+        T::NAME
+    }
+
+    fn definition(&self) -> Value {
+        json!({
+            "name": T::NAME,
+            "description": T::DESCRIPTION,
+            "inputSchema": input_schema::<T::Args>(),
+            "outputSchema": T::output_schema(),
+            "annotations": self.annotations().to_json(T::TITLE)
+        })
+    }
+
+    async fn invoke(&self, arguments: &Value, conn: &mut Connection) -> Result<ToolOutput, String> {
+        let args = parse_args(arguments)?;
+        self.call(args, conn).await
+    }
+}
+
+/// JSON Schema of a tool's argument struct, with field doc comments as
+/// descriptions.
+fn input_schema<A: JsonSchema>() -> Value {
+    let mut schema = SchemaSettings::draft2020_12()
+        .with(|s| {
+            s.meta_schema = None;
+            s.inline_subschemas = true;
+        })
+        .into_generator()
+        .into_root_schema_for::<A>();
+    // The struct's name and doc comment describe Rust code, not the tool.
+    schema.remove("title");
+    schema.remove("description");
+    schema
+        .ensure_object()
+        .entry("properties")
+        .or_insert_with(|| json!({}));
+    schema.to_value()
+}
+
+/// Deserialize tool arguments, naming the offending field on error.
+fn parse_args<A: DeserializeOwned>(arguments: &Value) -> Result<A, String> {
+    let empty = json!({});
+    let arguments = if arguments.is_null() {
+        &empty
+    } else {
+        arguments
+    };
+    serde_path_to_error::deserialize(arguments).map_err(|e| {
+        let path = e.path().to_string();
+        if path == "." {
+            format!("Invalid arguments: {}", e.inner())
+        } else {
+            format!("Invalid argument `{}`: {}", path, e.inner())
         }
-        "query" => {
-            let sql = get_str(arguments, "sql")?;
-            let max_rows = arguments
-                .get("max_rows")
-                .and_then(|v| v.as_u64())
-                .unwrap_or(100) as usize;
-            query::execute_query(conn, &sql, max_rows).await
+    })
+}
+
+/// Arguments of tools that take nothing but the output format.
+#[derive(Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct FormatArgs {
+    #[serde(default)]
+    pub format: Format,
+}
+
+/// Arguments of tools that work on one database.
+#[derive(Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct DatabaseArgs {
+    /// Database name
+    pub database: String,
+    #[serde(default)]
+    pub format: Format,
+}
+
+/// The tools served by `tools/list` and `tools/call`.
+pub struct Registry {
+    tools: Vec<Box<dyn DynTool>>,
+}
+
+impl Registry {
+    /// Every built-in tool.
+    pub fn builtin(args: &Args) -> Self {
+        Self {
+            tools: vec![
+                Box::new(schema::ListDatabases),
+                Box::new(schema::ListTables),
+                Box::new(schema::DescribeTable),
+                Box::new(query::Query::new(args)),
+                Box::new(query::QueryPlan),
+                Box::new(monitor::ActiveSessions),
+                Box::new(monitor::BlockingChains),
+                Box::new(monitor::IndexUsage),
+                Box::new(monitor::TableSizes),
+                Box::new(monitor::PoolStats),
+                Box::new(monitor::ServerInfo),
+            ],
         }
-        "query_plan" => {
-            let sql = get_str(arguments, "sql")?;
-            query::query_plan(conn, &sql).await
+    }
+
+    /// The built-in tools, `export_query` if `--export-dir` is set, and the
+    /// template tools from `--tools-file`.
+    pub fn load(args: &Args) -> Result<Self, String> {
+        let mut registry = Self::builtin(args);
+        if let Some(dir) = &args.export_dir {
+            registry
+                .tools
+                .push(Box::new(export::ExportQuery::new(dir)?));
         }
-        "active_sessions" => monitor::active_sessions(conn).await,
-        "blocking_chains" => monitor::blocking_chains(conn).await,
-        "index_usage" => {
-            let db = arguments.get("database").and_then(|v| v.as_str());
-            monitor::index_usage(conn, db).await
+        if let Some(path) = &args.tools_file {
+            for tool in custom::load(path)? {
+                if registry.tools.iter().any(|t| t.name() == tool.name()) {
+                    return Err(format!(
+                        "{}: tool '{}' is already defined",
+                        path,
+                        tool.name()
+                    ));
+                }
+                registry.tools.push(Box::new(tool));
+            }
         }
-        "table_sizes" => {
-            let db = get_str(arguments, "database")?;
-            monitor::table_sizes(conn, &db).await
+        Ok(registry)
+    }
+
+    pub fn definitions(&self) -> Value {
+        Value::Array(self.tools.iter().map(|t| t.definition()).collect())
+    }
+
+    pub async fn dispatch(
+        &self,
+        tool_name: &str,
+        arguments: &Value,
+        conn: &mut Connection,
+    ) -> Result<ToolOutput, String> {
+        match self.tools.iter().find(|t| t.name() == tool_name) {
+            Some(tool) => tool.invoke(arguments, conn).await,
+            None => Err(format!("Unknown tool: {}", tool_name)),
         }
-        "server_info" => monitor::server_info(conn).await,
-        _ => Err(format!("Unknown tool: {}", tool_name)),
     }
 }
 
-fn get_str(args: &Value, key: &str) -> Result<String, String> {
-    args.get(key)
-        .and_then(|v| v.as_str())
-        .map(|s| s.to_string())
-        .ok_or_else(|| format!("Missing required parameter: {}", key))
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    fn annotations(flags: &[&str], tool: &str) -> Value {
+        let args = Args::parse_from(std::iter::once("prowl").chain(flags.iter().copied()));
+        let definitions = Registry::builtin(&args).definitions();
+        definitions
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|t| t["name"] == tool)
+            .unwrap()["annotations"]
+            .clone()
+    }
+
+    #[test]
+    fn query_is_read_only_only_when_enforced() {
+        let lexer_only = annotations(&[], "query");
+        assert_eq!(lexer_only["readOnlyHint"], false);
+        assert_eq!(lexer_only["idempotentHint"], false);
+        let intent_only = annotations(&["--read-only-intent"], "query");
+        assert_eq!(intent_only["readOnlyHint"], false);
+        assert_eq!(intent_only["idempotentHint"], false);
+        let enforced = annotations(&["--rollback-queries"], "query");
+        assert_eq!(enforced["readOnlyHint"], true);
+        assert_eq!(enforced["idempotentHint"], true);
+        assert_eq!(annotations(&[], "list_tables")["readOnlyHint"], true);
+    }
+
+    #[test]
+    fn unknown_arguments_are_rejected() {
+        let err = parse_args::<DatabaseArgs>(&json!({ "database": "db", "schema": "x" }))
+            .err()
+            .unwrap();
+        assert_eq!(
+            err,
+            "Invalid argument `schema`: unknown field `schema`, expected `database` or `format`"
+        );
+        let err = parse_args::<DatabaseArgs>(&json!({ "database": 1 }))
+            .err()
+            .unwrap();
+        assert!(
+            err.starts_with("Invalid argument `database`: invalid type"),
+            "{}",
+            err
+        );
+        assert!(parse_args::<FormatArgs>(&Value::Null).is_ok());
+        assert_eq!(
+            input_schema::<DatabaseArgs>()["additionalProperties"],
+            false
+        );
+    }
 }
@@ -1,18 +1,27 @@
+use async_trait::async_trait;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+
 use crate::connection::Connection;
-use crate::tools::schema::query_to_markdown;
+use crate::tools::render::{Cell, Format, Results, Table, TableColumn};
+use crate::tools::schema::{in_database, query_to_output};
+use crate::tools::{pool_stats_schema, DatabaseArgs, FormatArgs, Tool, ToolOutput};
 
-pub async fn active_sessions(conn: &mut Connection) -> Result<String, String> {
+pub async fn active_sessions(conn: &mut Connection, format: Format) -> Result<ToolOutput, String> {
     let sql = "SELECT s.session_id, s.login_name, s.status, \
                r.command, r.wait_type, r.blocking_session_id, \
+               r.total_elapsed_time AS elapsed_ms, \
+               s.host_name, s.program_name, \
                s.cpu_time, s.reads, s.writes \
                FROM sys.dm_exec_sessions s \
                LEFT JOIN sys.dm_exec_requests r ON s.session_id = r.session_id \
                WHERE s.is_user_process = 1 \
                ORDER BY s.cpu_time DESC";
-    query_to_markdown(conn, sql, None).await
+    query_to_output(conn, sql, &[], None, format).await
 }
 
-pub async fn blocking_chains(conn: &mut Connection) -> Result<String, String> {
+pub async fn blocking_chains(conn: &mut Connection, format: Format) -> Result<ToolOutput, String> {
     let sql = r#"SELECT
     r.session_id AS blocked_session,
     r.blocking_session_id AS blocking_session,
@@ -27,20 +36,15 @@ JOIN sys.dm_exec_sessions s ON r.session_id = s.session_id
 LEFT JOIN sys.dm_exec_sessions bs ON r.blocking_session_id = bs.session_id
 WHERE r.blocking_session_id <> 0
 ORDER BY r.blocking_session_id, r.session_id"#;
-    query_to_markdown(conn, sql, None).await
+    query_to_output(conn, sql, &[], None, format).await
 }
 
-pub async fn index_usage(conn: &mut Connection, database: Option<&str>) -> Result<String, String> {
-    let db_filter = match database {
-        Some(db) => {
-            let db = db.replace('\'', "''");
-            format!("AND DB_NAME(d.database_id) = '{}'", db)
-        }
-        None => String::new(),
-    };
-
-    let sql = format!(
-        r#"-- Missing indexes
+pub async fn index_usage(
+    conn: &mut Connection,
+    database: Option<&str>,
+    format: Format,
+) -> Result<ToolOutput, String> {
+    let sql = r#"-- Missing indexes
 SELECT TOP 20
     DB_NAME(d.database_id) AS [database],
     d.equality_columns,
@@ -52,20 +56,19 @@ SELECT TOP 20
 FROM sys.dm_db_missing_index_details d
 JOIN sys.dm_db_missing_index_groups g ON d.index_handle = g.index_handle
 JOIN sys.dm_db_missing_index_group_stats gs ON g.index_group_handle = gs.group_handle
-WHERE 1=1 {}
-ORDER BY improvement_measure DESC"#,
-        db_filter
-    );
+WHERE @P1 IS NULL OR DB_NAME(d.database_id) = @P1
+ORDER BY improvement_measure DESC"#;
 
-    query_to_markdown(conn, &sql, Some(20)).await
+    query_to_output(conn, sql, &[&database], Some(20), format).await
 }
 
-pub async fn table_sizes(conn: &mut Connection, database: &str) -> Result<String, String> {
-    let db = database.replace('\'', "''").replace(']', "]]");
-    let sql = format!(
-        r#"USE [{db}];
-
-SELECT
+pub async fn table_sizes(
+    conn: &mut Connection,
+    database: &str,
+    format: Format,
+) -> Result<ToolOutput, String> {
+    let sql = in_database(
+        r#"SELECT
     s.name AS [schema],
     t.name AS [table],
     SUM(p.rows) AS row_count,
@@ -80,13 +83,13 @@ JOIN sys.allocation_units a ON p.partition_id = a.container_id
 WHERE i.index_id <= 1
 GROUP BY s.name, t.name
 ORDER BY SUM(a.total_pages) DESC"#,
-        db = db
+        &[],
     );
 
-    query_to_markdown(conn, &sql, None).await
+    query_to_output(conn, &sql, &[&database], None, format).await
 }
 
-pub async fn server_info(conn: &mut Connection) -> Result<String, String> {
+pub async fn server_info(conn: &mut Connection, format: Format) -> Result<ToolOutput, String> {
     let sql = r#"SELECT
     @@VERSION AS [version],
     @@SERVERNAME AS [server_name],
@@ -95,5 +98,170 @@ pub async fn server_info(conn: &mut Connection) -> Result<String, String> {
     CAST(SERVERPROPERTY('ProductLevel') AS nvarchar(256)) AS [product_level],
     (SELECT compatibility_level FROM sys.databases WHERE name = DB_NAME()) AS [compatibility_level]"#;
 
-    query_to_markdown(conn, sql, None).await
+    query_to_output(conn, sql, &[], None, format).await
+}
+
+pub fn pool_stats(conn: &Connection, format: Format) -> Result<ToolOutput, String> {
+    let (stats, idle, in_use, size) = conn.pool_stats();
+    let rows = [
+        ("size", size as u64),
+        ("in_use", in_use as u64),
+        ("idle", idle as u64),
+        ("opened", stats.opened),
+        ("reused", stats.reused),
+        ("closed_idle", stats.closed_idle),
+        ("closed_expired", stats.closed_expired),
+        ("closed_unhealthy", stats.closed_unhealthy),
+        ("closed_context", stats.closed_context),
+        ("closed_transaction", stats.closed_transaction),
+    ];
+
+    let mut structured = Map::new();
+    for (name, value) in rows {
+        structured.insert(name.to_string(), json!(value));
+    }
+    let table = Table {
+        title: "Pool stats".to_string(),
+        columns: vec![
+            TableColumn {
+                name: "metric".to_string(),
+                sql_type: "nvarchar",
+            },
+            TableColumn {
+                name: "value".to_string(),
+                sql_type: "bigint",
+            },
+        ],
+        rows: rows
+            .iter()
+            .map(|&(name, value)| {
+                vec![
+                    Cell::new(Some(name.to_string()), json!(name)),
+                    Cell::new(Some(value.to_string()), json!(value)),
+                ]
+            })
+            .collect(),
+        skipped: 0,
+        truncated: false,
+        cut_cells: Vec::new(),
+        notes: Vec::new(),
+    };
+    let results = Results {
+        tables: vec![table],
+        fields: Map::new(),
+        notes: Vec::new(),
+    };
+    Ok(ToolOutput {
+        content: format.renderer().render(&results),
+        structured: Value::Object(structured),
+    })
+}
+
+pub struct ActiveSessions;
+
+#[async_trait]
+impl Tool for ActiveSessions {
+    type Args = FormatArgs;
+
+    const NAME: &'static str = "active_sessions";
+    const TITLE: &'static str = "Active sessions";
+    const DESCRIPTION: &'static str = "Show active user sessions on the SQL Server";
+
+    async fn call(&self, args: FormatArgs, conn: &mut Connection) -> Result<ToolOutput, String> {
+        active_sessions(conn, args.format).await
+    }
+}
+
+pub struct BlockingChains;
+
+#[async_trait]
+impl Tool for BlockingChains {
+    type Args = FormatArgs;
+
+    const NAME: &'static str = "blocking_chains";
+    const TITLE: &'static str = "Blocking chains";
+    const DESCRIPTION: &'static str = "Show blocking chains — sessions blocking other sessions";
+
+    async fn call(&self, args: FormatArgs, conn: &mut Connection) -> Result<ToolOutput, String> {
+        blocking_chains(conn, args.format).await
+    }
+}
+
+pub struct IndexUsage;
+
+#[derive(Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct IndexUsageArgs {
+    /// Only show missing indexes in this database (default: all databases)
+    database: Option<String>,
+    #[serde(default)]
+    format: Format,
+}
+
+#[async_trait]
+impl Tool for IndexUsage {
+    type Args = IndexUsageArgs;
+
+    const NAME: &'static str = "index_usage";
+    const TITLE: &'static str = "Missing indexes";
+    const DESCRIPTION: &'static str =
+        "Show the top 20 missing indexes suggested by the query optimizer, by estimated improvement";
+
+    async fn call(
+        &self,
+        args: IndexUsageArgs,
+        conn: &mut Connection,
+    ) -> Result<ToolOutput, String> {
+        index_usage(conn, args.database.as_deref(), args.format).await
+    }
+}
+
+pub struct TableSizes;
+
+#[async_trait]
+impl Tool for TableSizes {
+    type Args = DatabaseArgs;
+
+    const NAME: &'static str = "table_sizes";
+    const TITLE: &'static str = "Table sizes";
+    const DESCRIPTION: &'static str = "Show space used per table in a database";
+
+    async fn call(&self, args: DatabaseArgs, conn: &mut Connection) -> Result<ToolOutput, String> {
+        table_sizes(conn, &args.database, args.format).await
+    }
+}
+
+pub struct PoolStats;
+
+#[async_trait]
+impl Tool for PoolStats {
+    type Args = FormatArgs;
+
+    const NAME: &'static str = "pool_stats";
+    const TITLE: &'static str = "Connection pool stats";
+    const DESCRIPTION: &'static str =
+        "Show prowl's connection pool usage: sessions in use, idle, opened, reused and closed";
+
+    fn output_schema() -> Value {
+        pool_stats_schema()
+    }
+
+    async fn call(&self, args: FormatArgs, conn: &mut Connection) -> Result<ToolOutput, String> {
+        pool_stats(conn, args.format)
+    }
+}
+
+pub struct ServerInfo;
+
+#[async_trait]
+impl Tool for ServerInfo {
+    type Args = FormatArgs;
+
+    const NAME: &'static str = "server_info";
+    const TITLE: &'static str = "Server info";
+    const DESCRIPTION: &'static str = "Show SQL Server version, edition, and configuration";
+
+    async fn call(&self, args: FormatArgs, conn: &mut Connection) -> Result<ToolOutput, String> {
+        server_info(conn, args.format).await
+    }
 }
@@ -0,0 +1,255 @@
+use claw::numeric::Numeric;
+use claw::Uuid;
+use serde_json::Value;
+
+/// A query parameter decoded from tool arguments, bound as `@P1..@Pn`.
+pub enum SqlParam {
+    Int(Option<i64>),
+    Float(Option<f64>),
+    Bit(Option<bool>),
+    Text(Option<String>),
+    Decimal(Option<Numeric>),
+    Guid(Option<Uuid>),
+    Binary(Option<Vec<u8>>),
+}
+
+impl SqlParam {
+    pub fn as_sql(&self) -> &dyn claw::IntoSql {
+        match self {
+            SqlParam::Int(v) => v,
+            SqlParam::Float(v) => v,
+            SqlParam::Bit(v) => v,
+            SqlParam::Text(v) => v,
+            SqlParam::Decimal(v) => v,
+            SqlParam::Guid(v) => v,
+            SqlParam::Binary(v) => v,
+        }
+    }
+}
+
+/// Parse the `params` tool argument: items are either plain JSON scalars
+/// (type inferred) or `{ "value": ..., "type": "<sql type>" }`.
+pub fn parse_params(items: &[Value]) -> Result<Vec<SqlParam>, String> {
+    items
+        .iter()
+        .enumerate()
+        .map(|(i, item)| parse_param(item).map_err(|e| format!("params[{}]: {}", i, e)))
+        .collect()
+}
+
+fn parse_param(item: &Value) -> Result<SqlParam, String> {
+    match item {
+        Value::Object(obj) => {
+            let value = obj.get("value").unwrap_or(&Value::Null);
+            match obj.get("type").and_then(|t| t.as_str()) {
+                Some(ty) => parse_typed(value, &ty.to_lowercase()),
+                None => parse_inferred(value),
+            }
+        }
+        other => parse_inferred(other),
+    }
+}
+
+fn parse_inferred(value: &Value) -> Result<SqlParam, String> {
+    match value {
+        Value::Null => Ok(SqlParam::Text(None)),
+        Value::Bool(b) => Ok(SqlParam::Bit(Some(*b))),
+        Value::Number(n) => match n.as_i64() {
+            Some(i) => Ok(SqlParam::Int(Some(i))),
+            None => Ok(SqlParam::Float(n.as_f64())),
+        },
+        Value::String(s) => Ok(SqlParam::Text(Some(s.clone()))),
+        _ => Err("expected a scalar value or { value, type }".to_string()),
+    }
+}
+
+/// Decode `value` as SQL type `ty` (lowercase, e.g. `int` or `nvarchar(50)`).
+pub fn parse_typed(value: &Value, ty: &str) -> Result<SqlParam, String> {
+    // Strip any length/precision suffix, e.g. nvarchar(50) or decimal(10,2).
+    let base = ty.split('(').next().unwrap_or(ty).trim();
+    let text = match value {
+        Value::Null => None,
+        Value::String(s) => Some(s.clone()),
+        Value::Number(n) => Some(n.to_string()),
+        Value::Bool(b) => Some(if *b { "1" } else { "0" }.to_string()),
+        _ => return Err("expected a scalar value".to_string()),
+    };
+
+    let param = match base {
+        "tinyint" | "smallint" | "int" | "bigint" => SqlParam::Int(
+            text.map(|t| {
+                t.parse::<i64>()
+                    .map_err(|_| format!("'{}' is not an integer", t))
+            })
+            .transpose()?,
+        ),
+        "real" | "float" => SqlParam::Float(
+            text.map(|t| {
+                t.parse::<f64>()
+                    .map_err(|_| format!("'{}' is not a number", t))
+            })
+            .transpose()?,
+        ),
+        "bit" => SqlParam::Bit(
+            text.map(|t| match t.to_lowercase().as_str() {
+                "1" | "true" => Ok(true),
+                "0" | "false" => Ok(false),
+                _ => Err(format!("'{}' is not a bit", t)),
+            })
+            .transpose()?,
+        ),
+        "decimal" | "numeric" | "money" | "smallmoney" => {
+            SqlParam::Decimal(text.map(|t| parse_numeric(&t)).transpose()?)
+        }
+        "uniqueidentifier" => SqlParam::Guid(
+            text.map(|t| Uuid::parse_str(&t).map_err(|_| format!("'{}' is not a GUID", t)))
+                .transpose()?,
+        ),
+        "binary" | "varbinary" => SqlParam::Binary(text.map(|t| parse_hex(&t)).transpose()?),
+        // Character, date/time and xml values are sent as nvarchar and
+        // converted by the server where they are used.
+        "char" | "varchar" | "nchar" | "nvarchar" | "text" | "ntext" | "xml" | "date" | "time"
+        | "datetime" | "datetime2" | "smalldatetime" | "datetimeoffset" => SqlParam::Text(text),
+        other => return Err(format!("unsupported type '{}'", other)),
+    };
+
+    Ok(param)
+}
+
+/// Parse a decimal such as `12.50`, `-.5` or `1.5e-3` into a value and scale.
+fn parse_numeric(text: &str) -> Result<Numeric, String> {
+    let err = || format!("'{}' is not a decimal", text);
+    let trimmed = text.trim();
+    let (mantissa, exponent) = match trimmed.split_once(['e', 'E']) {
+        Some((m, e)) => (m, e.parse::<i32>().map_err(|_| err())?),
+        None => (trimmed, 0),
+    };
+    let (int_part, frac_part) = mantissa.split_once('.').unwrap_or((mantissa, ""));
+    if !frac_part.chars().all(|c| c.is_ascii_digit()) {
+        return Err(err());
+    }
+    let digits = format!("{}{}", int_part, frac_part);
+    let mut value = digits.parse::<i128>().map_err(|_| err())?;
+
+    // 1.5e-3 is 15 with scale 4; 1.5e3 is 1500 with scale 0.
+    let mut scale = frac_part.len() as i64 - exponent as i64;
+    if scale < 0 {
+        value = 10i128
+            .checked_pow(u32::try_from(-scale).map_err(|_| err())?)
+            .and_then(|p| value.checked_mul(p))
+            .ok_or_else(err)?;
+        scale = 0;
+    }
+    if scale > 38 || value.unsigned_abs() >= 10u128.pow(38) {
+        return Err(err());
+    }
+    Ok(Numeric::new_with_scale(value, scale as u8))
+}
+
+fn parse_hex(text: &str) -> Result<Vec<u8>, String> {
+    let hex = text
+        .strip_prefix("0x")
+        .or_else(|| text.strip_prefix("0X"))
+        .unwrap_or(text);
+    if !hex.is_ascii() || !hex.len().is_multiple_of(2) {
+        return Err(format!("'{}' is not a hex string", text));
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| {
+            u8::from_str_radix(&hex[i..i + 2], 16)
+                .map_err(|_| format!("'{}' is not a hex string", text))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn decimal(text: &str) -> (i128, u8) {
+        let n = parse_numeric(text).unwrap();
+        (n.value(), n.scale())
+    }
+
+    #[test]
+    fn decimals_keep_their_scale() {
+        assert_eq!(decimal("12.50"), (1250, 2));
+        assert_eq!(decimal("-0.5"), (-5, 1));
+        assert_eq!(decimal("-.5"), (-5, 1));
+        assert_eq!(decimal("42"), (42, 0));
+        assert!(parse_numeric("1.2.3").is_err());
+        assert!(parse_numeric("abc").is_err());
+        assert!(parse_numeric("").is_err());
+    }
+
+    #[test]
+    fn decimals_accept_exponents() {
+        assert_eq!(decimal("1e-3"), (1, 3));
+        assert_eq!(decimal("1.5E-3"), (15, 4));
+        assert_eq!(decimal("1.5e3"), (1500, 0));
+        assert_eq!(decimal("2.50e+1"), (250, 1));
+        assert_eq!(decimal("-1E2"), (-100, 0));
+        assert!(parse_numeric("1e").is_err());
+        assert!(parse_numeric("1e-39").is_err());
+        assert!(parse_numeric("1e38").is_err());
+        assert!(parse_numeric("1e99999999999").is_err());
+    }
+
+    #[test]
+    fn inferred_params() {
+        let params =
+            parse_params(&[json!(1), json!(1.5), json!(true), json!(null), json!("x")]).unwrap();
+        assert!(matches!(params[0], SqlParam::Int(Some(1))));
+        assert!(matches!(params[1], SqlParam::Float(Some(f)) if f == 1.5));
+        assert!(matches!(params[2], SqlParam::Bit(Some(true))));
+        assert!(matches!(params[3], SqlParam::Text(None)));
+        assert!(matches!(&params[4], SqlParam::Text(Some(s)) if s == "x"));
+    }
+
+    #[test]
+    fn typed_params() {
+        let params = parse_params(&[
+            json!({ "value": "7", "type": "INT" }),
+            json!({ "value": 1e-3, "type": "decimal(10,3)" }),
+            json!({ "value": "19.99", "type": "money" }),
+            json!({ "value": "0xCAFE", "type": "varbinary(max)" }),
+            json!({ "value": "2024-01-02", "type": "date" }),
+            json!({ "value": null, "type": "uniqueidentifier" }),
+            json!({ "value": "false", "type": "bit" }),
+            json!({ "value": 42 }),
+        ])
+        .unwrap();
+        assert!(matches!(params[0], SqlParam::Int(Some(7))));
+        assert!(
+            matches!(&params[1], SqlParam::Decimal(Some(n)) if n.value() == 1 && n.scale() == 3)
+        );
+        assert!(matches!(&params[2], SqlParam::Decimal(Some(n)) if n.value() == 1999));
+        assert!(matches!(&params[3], SqlParam::Binary(Some(b)) if b == &[0xca, 0xfe]));
+        assert!(matches!(&params[4], SqlParam::Text(Some(s)) if s == "2024-01-02"));
+        assert!(matches!(params[5], SqlParam::Guid(None)));
+        assert!(matches!(params[6], SqlParam::Bit(Some(false))));
+        assert!(matches!(params[7], SqlParam::Int(Some(42))));
+    }
+
+    #[test]
+    fn bad_params_name_their_index() {
+        let err = |items: &[Value]| parse_params(items).err().unwrap();
+        assert_eq!(
+            err(&[json!(1), json!({ "value": "x", "type": "int" })]),
+            "params[1]: 'x' is not an integer"
+        );
+        assert_eq!(
+            err(&[json!({ "value": 1, "type": "geography" })]),
+            "params[0]: unsupported type 'geography'"
+        );
+        assert_eq!(
+            err(&[json!([1])]),
+            "params[0]: expected a scalar value or { value, type }"
+        );
+        assert!(parse_typed(&json!("0xABC"), "binary").is_err());
+        assert!(parse_typed(&json!("maybe"), "bit").is_err());
+        assert!(parse_typed(&json!("not-a-guid"), "uniqueidentifier").is_err());
+    }
+}
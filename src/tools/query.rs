@@ -1,45 +1,456 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use claw::Column;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use serde_json::Value;
+use tracing::warn;
+
 use crate::connection::Connection;
-use crate::tools::schema::query_to_markdown;
-
-const BLOCKED_KEYWORDS: &[&str] = &[
-    "INSERT", "UPDATE", "DELETE", "DROP", "ALTER", "CREATE", "TRUNCATE", "EXEC", "EXECUTE",
-];
-
-fn is_read_only(sql: &str) -> bool {
-    let upper = sql.to_uppercase();
-    for keyword in BLOCKED_KEYWORDS {
-        // Check for keyword as a whole word (preceded by start/whitespace/semicolon)
-        for part in upper.split_whitespace() {
-            if part.trim_matches(|c: char| !c.is_alphanumeric()) == *keyword {
-                return false;
-            }
+use crate::server::random_id;
+use crate::tools::params::{parse_params, SqlParam};
+use crate::tools::render::{Format, Results};
+use crate::tools::schema::{fetch_result_sets, query_to_markdown, Limits};
+use crate::tools::{
+    query_output_schema, tsql, Tool, ToolAnnotations, ToolOutput, LEXER_CHECKED, READ_ONLY,
+};
+use crate::Args;
+
+pub fn ensure_read_only(sql: &str) -> Result<(), String> {
+    tsql::check_read_only(sql).map_err(|reason| {
+        warn!(%reason, "Blocked query");
+        format!(
+            "Write operations are not allowed ({}). Only SELECT and read-only queries are permitted.",
+            reason
+        )
+    })
+}
+
+/// Appended to a rolled-back batch: returns a row only if the transaction
+/// wrote to the log, i.e. some statement modified data.
+const MODIFICATION_PROBE: &str = "SELECT 1 AS prowl_modified \
+     FROM sys.dm_tran_database_transactions \
+     WHERE transaction_id = (SELECT transaction_id FROM sys.dm_tran_current_transaction) \
+     AND database_transaction_log_record_count > 0;";
+
+/// Run a read-only batch. Also returns how many rows of the first result
+/// set were shown, if a limit cut it.
+pub async fn execute_query(
+    conn: &mut Connection,
+    database: Option<&str>,
+    sql: &str,
+    params: &[SqlParam],
+    limits: Limits,
+    timeout_seconds: Option<u64>,
+) -> Result<(Results, Option<usize>), String> {
+    ensure_read_only(sql)?;
+
+    conn.set_database(database);
+    let timeout = call_timeout(conn, timeout_seconds);
+
+    let params: Vec<&dyn claw::IntoSql> = params.iter().map(|p| p.as_sql()).collect();
+    let (wrapped, layers) = enforced_batch(conn, sql)?;
+
+    let batch = if conn.args().rollback_queries {
+        let batch = fetch_result_sets(conn, &wrapped, &params, limits, timeout).await;
+        rollback_open_transaction(conn).await;
+        let mut batch = batch?;
+
+        // A batch cancelled by a limit may not have reached the probe.
+        let reached_probe = batch.sets.last().is_some_and(|set| is_probe(&set.columns));
+        if reached_probe && batch.sets.pop().is_some_and(|probe| !probe.rows.is_empty()) {
+            return Err(modified_data());
         }
+
+        batch
+    } else {
+        fetch_result_sets(conn, &wrapped, &params, limits, timeout).await?
+    };
+
+    let cut = batch
+        .sets
+        .first()
+        .filter(|set| set.truncated_by.is_some())
+        .map(|set| set.rows.len());
+    let database = conn.current_database().unwrap_or("(unknown)");
+    let mut results = Results::from_batches(&[&batch]);
+    results.notes.push(format!(
+        "Database: {} · Read-only enforcement: {}",
+        database,
+        layers.join(", ")
+    ));
+    results
+        .fields
+        .insert("database".to_string(), Value::from(database));
+    results
+        .fields
+        .insert("read_only_enforcement".to_string(), Value::from(layers));
+
+    Ok((results, cut))
+}
+
+/// Wrap a read-only batch in the runtime enforcement layers configured for
+/// the server, and list every layer that applies.
+///
+/// With `--rollback-queries` the batch runs in a transaction that is always
+/// rolled back, followed by a result set from the modification probe; see
+/// [`is_probe`]. Batches that could exit before the probe are refused.
+pub fn enforced_batch(conn: &Connection, sql: &str) -> Result<(String, Vec<&'static str>), String> {
+    let mut layers = vec!["T-SQL lexer"];
+    let wrapped = if conn.args().rollback_queries {
+        tsql::check_runs_to_end(sql).map_err(|reason| {
+            warn!(%reason, "Blocked query");
+            format!(
+                "Not allowed with --rollback-queries ({}): the enforcement transaction \
+                 must be rolled back after the last statement.",
+                reason
+            )
+        })?;
+        layers.push("rolled-back transaction");
+        format!(
+            "SET XACT_ABORT ON; SET TRANSACTION ISOLATION LEVEL READ UNCOMMITTED; SET NOCOUNT ON;\n\
+             BEGIN TRANSACTION;\n{}\n;\n{}\nROLLBACK TRANSACTION;",
+            sql, MODIFICATION_PROBE
+        )
+    } else {
+        format!(
+            "SET TRANSACTION ISOLATION LEVEL READ UNCOMMITTED; SET NOCOUNT ON;\n{}",
+            sql
+        )
+    };
+    if conn.args().read_only_intent {
+        layers.push("ApplicationIntent=ReadOnly");
     }
-    true
+    Ok((wrapped, layers))
 }
 
-pub async fn execute_query(
+/// Whether a result set is the modification probe's. It is the last set of
+/// a rolled-back batch, unless the batch was cancelled before reaching it;
+/// then its session was closed, which rolls the transaction back.
+pub fn is_probe(columns: &[Column]) -> bool {
+    columns
+        .first()
+        .is_some_and(|c| c.name() == "prowl_modified")
+}
+
+/// The error for a batch the modification probe caught writing.
+pub fn modified_data() -> String {
+    warn!("Blocked query: batch modified data and was rolled back");
+    "Write operations are not allowed (the batch modified data inside the \
+     enforcement transaction; all changes were rolled back)."
+        .to_string()
+}
+
+/// Make sure an enforcement batch leaves no transaction open, however it
+/// ended. A cancelled batch has no session left to clean up.
+pub async fn rollback_open_transaction(conn: &mut Connection) {
+    if !conn.has_session() {
+        return;
+    }
+    if let Err(e) =
+        query_to_markdown(conn, "IF @@TRANCOUNT > 0 ROLLBACK TRANSACTION;", &[], None).await
+    {
+        warn!("Rollback after query failed: {}", e);
+    }
+}
+
+/// Whether the login can read `sys.dm_tran_database_transactions`, which the
+/// modification probe needs.
+pub async fn can_view_server_state(conn: &mut Connection) -> Result<bool, String> {
+    let client = conn.get_client().await?;
+    let params: Vec<&dyn claw::IntoSql> = vec![];
+    let row = client
+        .execute(
+            "SELECT HAS_PERMS_BY_NAME(NULL, NULL, 'VIEW SERVER STATE')",
+            &params,
+        )
+        .await
+        .map_err(|e| format!("Permission check failed: {}", e))?
+        .into_row()
+        .await
+        .map_err(|e| format!("Permission check failed: {}", e))?;
+    Ok(row.and_then(|r| r.get::<i32, _>(0)) == Some(1))
+}
+
+pub async fn query_plan(
     conn: &mut Connection,
+    database: Option<&str>,
     sql: &str,
+    timeout_seconds: Option<u64>,
+    format: Format,
+) -> Result<ToolOutput, String> {
+    ensure_read_only(sql)?;
+
+    conn.set_database(database);
+    let timeout = call_timeout(conn, timeout_seconds);
+    let wrapped = format!("SET SHOWPLAN_TEXT ON;\n{}\nSET SHOWPLAN_TEXT OFF;", sql);
+
+    let batch = fetch_result_sets(conn, &wrapped, &[], Limits::default(), timeout).await?;
+    Ok(Results::from_batches(&[&batch]).into_output(format))
+}
+
+/// A per-call `timeout_seconds` overrides the global `--query-timeout`;
+/// 0 disables the timeout for that call.
+pub fn call_timeout(conn: &Connection, timeout_seconds: Option<u64>) -> Option<Duration> {
+    match timeout_seconds {
+        Some(0) => None,
+        Some(secs) => Some(Duration::from_secs(secs)),
+        None => conn.query_timeout(),
+    }
+}
+
+/// How long a `next_cursor` stays valid.
+const CURSOR_TTL: Duration = Duration::from_secs(600);
+/// Cursors kept at once; the one closest to expiry is dropped first.
+const MAX_CURSORS: usize = 1000;
+
+/// The `query` tool. Holds the cursors of truncated results.
+///
+/// A cursor names a page of a single `SELECT` with a top-level `ORDER BY`,
+/// which is re-run with `OFFSET ... FETCH` for each page; see
+/// [`tsql::with_offset`]. Other batches are not paged.
+pub struct Query {
+    cursors: Mutex<HashMap<String, Page>>,
+    /// Whether `--rollback-queries` backs the lexer. `--read-only-intent`
+    /// does not count: it only routes to a readable secondary, and a
+    /// primary or standalone server still accepts writes.
+    enforced: bool,
+}
+
+/// The next page of a truncated result: the same call, re-executed from
+/// row `start`.
+struct Page {
+    args: QueryArgs,
+    start: usize,
+    /// The peer that ran the query; only it can fetch the page.
+    owner: Option<String>,
+    expires: Instant,
+}
+
+#[derive(Clone, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct QueryArgs {
+    /// SQL query to execute. Reference parameters as @P1, @P2, ... Required unless `cursor` is given
+    sql: Option<String>,
+    /// `next_cursor` from a truncated result: returns the next page of that query. The other arguments are taken from the cursor
+    cursor: Option<String>,
+    /// Database to run the query in (default: the server's --database)
+    database: Option<String>,
+    /// Values bound to @P1..@Pn. Each item is a JSON scalar, or { "value": ..., "type": "<sql type>" } to set the SQL type explicitly
+    #[serde(default)]
+    params: Vec<Value>,
+    /// Maximum rows to return per result set (at least 1)
+    #[serde(default = "default_max_rows")]
     max_rows: usize,
-) -> Result<String, String> {
-    if !is_read_only(sql) {
-        return Err(
-            "Write operations are not allowed. Only SELECT and read-only queries are permitted."
-                .to_string(),
+    /// Stop showing rows once the shown cells reach this many bytes (default: --max-bytes, 0 disables)
+    max_bytes: Option<usize>,
+    /// Cut cell values longer than this many characters (default: --max-cell-chars, 0 disables)
+    max_cell_chars: Option<usize>,
+    /// Cancel the query after this many seconds and return partial results (default: --query-timeout, 0 disables)
+    timeout_seconds: Option<u64>,
+    /// Keep reading the batch after a limit cuts a result set, so the later statements of a multi-statement batch still return their result sets. By default the rest of the batch is cancelled at the first cut
+    #[serde(default)]
+    read_to_end: bool,
+    #[serde(default)]
+    format: Format,
+}
+
+fn default_max_rows() -> usize {
+    100
+}
+
+#[async_trait]
+impl Tool for Query {
+    type Args = QueryArgs;
+
+    const NAME: &'static str = "query";
+    const TITLE: &'static str = "Run read-only SQL";
+    const DESCRIPTION: &'static str = "Execute a read-only SQL batch and return every result set as a markdown table, or in the given `format`. Write operations are blocked.";
+
+    /// Read-only is only promised when the server enforces it at runtime.
+    fn annotations(&self) -> ToolAnnotations {
+        if self.enforced {
+            READ_ONLY
+        } else {
+            LEXER_CHECKED
+        }
+    }
+
+    fn output_schema() -> Value {
+        query_output_schema()
+    }
+
+    async fn call(&self, args: QueryArgs, conn: &mut Connection) -> Result<ToolOutput, String> {
+        let (args, start) = match &args.cursor {
+            Some(cursor) => self.page(cursor, conn.peer())?,
+            None => (args, 0),
+        };
+        let sql = args
+            .sql
+            .as_deref()
+            .ok_or("Invalid arguments: missing field `sql`")?;
+        if args.max_rows == 0 {
+            return Err("Invalid argument `max_rows`: must be at least 1".to_string());
+        }
+        let params = parse_params(&args.params)?;
+        // One row past the page tells whether there are more.
+        let paged = tsql::with_offset(sql, start, args.max_rows + 1);
+        let mut limits = Limits::from_args(conn.args(), Some(args.max_rows));
+        limits.first_row = start;
+        if let Some(n) = args.max_bytes {
+            limits.max_bytes = Some(n).filter(|&n| n > 0);
+        }
+        if let Some(n) = args.max_cell_chars {
+            limits.max_cell_chars = Some(n).filter(|&n| n > 0);
+        }
+        // A paged query sends at most one row past the page; reading it is
+        // cheaper than closing the session to cancel.
+        limits.cancel_at_limit = !args.read_to_end && paged.is_none();
+        let (mut results, cut) = execute_query(
+            conn,
+            args.database.as_deref(),
+            paged.as_deref().unwrap_or(sql),
+            &params,
+            limits,
+            args.timeout_seconds,
+        )
+        .await?;
+
+        let format = args.format;
+        let cursor = match cut {
+            Some(shown) if paged.is_some() && shown > 0 => {
+                let owner = conn.peer().map(|p| p.to_string());
+                Some(self.save_page(args, start + shown, owner))
+            }
+            _ => None,
+        };
+        match (&cursor, cut) {
+            (Some(cursor), _) => results.notes.push(format!(
+                "More rows: call `query` with `cursor` \"{}\" for the next page",
+                cursor
+            )),
+            (None, Some(_)) => results.notes.push(
+                "More rows: only a single SELECT with an ORDER BY, and no TOP or OFFSET, \
+                 can be paged with a cursor"
+                    .to_string(),
+            ),
+            (None, None) => {}
+        }
+        results
+            .fields
+            .insert("next_cursor".to_string(), Value::from(cursor));
+        Ok(results.into_output(format))
+    }
+}
+
+impl Query {
+    pub fn new(args: &Args) -> Self {
+        Self {
+            cursors: Mutex::new(HashMap::new()),
+            enforced: args.rollback_queries,
+        }
+    }
+
+    /// The arguments and start of the page a cursor names. Other peers'
+    /// cursors are reported as unknown.
+    fn page(&self, cursor: &str, peer: Option<&str>) -> Result<(QueryArgs, usize), String> {
+        let cursors = self.cursors.lock().unwrap();
+        match cursors.get(cursor) {
+            Some(page) if page.expires > Instant::now() && page.owner.as_deref() == peer => {
+                Ok((page.args.clone(), page.start))
+            }
+            _ => Err(
+                "Invalid argument `cursor`: unknown or expired; run the query again".to_string(),
+            ),
+        }
+    }
+
+    /// Remember where the next page starts and return its cursor.
+    fn save_page(&self, mut args: QueryArgs, start: usize, owner: Option<String>) -> String {
+        args.cursor = None;
+        let cursor = random_id();
+        let now = Instant::now();
+        let mut cursors = self.cursors.lock().unwrap();
+        cursors.retain(|_, page| page.expires > now);
+        if cursors.len() >= MAX_CURSORS {
+            if let Some(oldest) = cursors
+                .iter()
+                .min_by_key(|(_, page)| page.expires)
+                .map(|(id, _)| id.clone())
+            {
+                cursors.remove(&oldest);
+            }
+        }
+        cursors.insert(
+            cursor.clone(),
+            Page {
+                args,
+                start,
+                owner,
+                expires: now + CURSOR_TTL,
+            },
         );
+        cursor
     }
+}
 
-    let wrapped = format!(
-        "SET TRANSACTION ISOLATION LEVEL READ UNCOMMITTED; SET NOCOUNT ON;\n{}",
-        sql
-    );
+pub struct QueryPlan;
 
-    query_to_markdown(conn, &wrapped, Some(max_rows)).await
+#[derive(Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct QueryPlanArgs {
+    /// SQL query to get execution plan for
+    sql: String,
+    /// Database to plan the query in (default: the server's --database)
+    database: Option<String>,
+    /// Cancel after this many seconds (default: --query-timeout, 0 disables)
+    timeout_seconds: Option<u64>,
+    #[serde(default)]
+    format: Format,
 }
 
-pub async fn query_plan(conn: &mut Connection, sql: &str) -> Result<String, String> {
-    let wrapped = format!("SET SHOWPLAN_TEXT ON;\n{}\nSET SHOWPLAN_TEXT OFF;", sql);
+#[async_trait]
+impl Tool for QueryPlan {
+    type Args = QueryPlanArgs;
+
+    const NAME: &'static str = "query_plan";
+    const TITLE: &'static str = "Show execution plan";
+    const DESCRIPTION: &'static str = "Show the execution plan for a SQL query";
+
+    async fn call(&self, args: QueryPlanArgs, conn: &mut Connection) -> Result<ToolOutput, String> {
+        query_plan(
+            conn,
+            args.database.as_deref(),
+            &args.sql,
+            args.timeout_seconds,
+            args.format,
+        )
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
 
-    query_to_markdown(conn, &wrapped, None).await
+    #[test]
+    fn cursors_belong_to_their_peer() {
+        let query = Query::new(&Args::parse_from(["prowl"]));
+        let args: QueryArgs = serde_json::from_value(serde_json::json!({
+            "sql": "SELECT a FROM t ORDER BY a"
+        }))
+        .unwrap();
+        let cursor = query.save_page(args, 100, Some("a".to_string()));
+
+        let (args, start) = query.page(&cursor, Some("a")).unwrap();
+        assert_eq!(args.sql.as_deref(), Some("SELECT a FROM t ORDER BY a"));
+        assert_eq!(start, 100);
+        assert!(query.page(&cursor, Some("b")).is_err());
+        assert!(query.page(&cursor, None).is_err());
+        assert!(query.page("unknown", Some("a")).is_err());
+    }
 }
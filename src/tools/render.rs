@@ -0,0 +1,667 @@
+use std::time::Duration;
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+
+use crate::tools::schema::{Batch, StopReason};
+use crate::tools::value::{limited_cell_to_json, render_limited, type_name};
+use crate::tools::ToolOutput;
+
+/// Output format of the text content: markdown tables, json (the result sets as one object), jsonl (one object per row), csv, or vertical (one `column | value` line per field, like psql's `\x`). jsonl and csv return each result set as its own content block, followed by a block of notes
+#[derive(Clone, Copy, Default, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    #[default]
+    Markdown,
+    Json,
+    Jsonl,
+    Csv,
+    Vertical,
+}
+
+impl Format {
+    pub fn renderer(self) -> &'static dyn Renderer {
+        match self {
+            Format::Markdown => &Markdown,
+            Format::Json => &Json,
+            Format::Jsonl => &Jsonl,
+            Format::Csv => &Csv,
+            Format::Vertical => &Vertical,
+        }
+    }
+}
+
+/// Turns a tool's results into its text content blocks.
+pub trait Renderer: Sync {
+    fn render(&self, results: &Results) -> Vec<String>;
+}
+
+/// What a tool returns, independent of the output format: typed result
+/// sets plus notes about how they were cut.
+pub struct Results {
+    pub tables: Vec<Table>,
+    /// Top-level fields of the structured content besides `result_sets`.
+    pub fields: Map<String, Value>,
+    /// Notes about the whole result, e.g. that it timed out.
+    pub notes: Vec<String>,
+}
+
+/// One result set.
+pub struct Table {
+    /// Heading shown when a result has several tables.
+    pub title: String,
+    pub columns: Vec<TableColumn>,
+    pub rows: Vec<Vec<Cell>>,
+    /// Rows of this set returned by earlier pages.
+    pub skipped: usize,
+    pub truncated: bool,
+    /// Columns with cells cut at `max_cell_chars`, and how many.
+    pub cut_cells: Vec<(String, usize)>,
+    /// Notes about this set, e.g. that it was truncated.
+    pub notes: Vec<String>,
+}
+
+pub struct TableColumn {
+    pub name: String,
+    /// SQL Server type name.
+    pub sql_type: &'static str,
+}
+
+/// A cell, already cut to `max_cell_chars`: its text for the text formats
+/// and its typed JSON value. NULL has no text and is `null`.
+pub struct Cell {
+    pub text: Option<String>,
+    pub value: Value,
+}
+
+impl Cell {
+    pub fn new(text: Option<String>, value: Value) -> Self {
+        Self { text, value }
+    }
+}
+
+impl Results {
+    /// The result sets of one or more batches, with the notes each batch
+    /// needs: where a page continues from, truncation, cut cells and
+    /// timeouts.
+    pub fn from_batches(batches: &[&Batch]) -> Self {
+        let mut tables = Vec::new();
+        let mut notes = Vec::new();
+        for batch in batches {
+            if batch.first_row > 0 {
+                notes.push(format!("Continuing from row {}", batch.first_row + 1));
+            }
+            for (i, set) in batch.sets.iter().enumerate() {
+                let skipped = if i == 0 { batch.first_row } else { 0 };
+                let max = batch.max_cell_chars;
+                let columns = set
+                    .columns
+                    .iter()
+                    .map(|c| TableColumn {
+                        name: c.name().to_string(),
+                        sql_type: type_name(c.column_type()),
+                    })
+                    .collect();
+                let rows = set
+                    .rows
+                    .iter()
+                    .map(|row| {
+                        row.cells()
+                            .map(|(col, data)| {
+                                let (text, _) = render_limited(col.column_type(), data, max);
+                                Cell::new(text, limited_cell_to_json(col.column_type(), data, max))
+                            })
+                            .collect()
+                    })
+                    .collect();
+                let cut_cells: Vec<(String, usize)> = set
+                    .columns
+                    .iter()
+                    .zip(&set.cut_cells)
+                    .filter(|(_, &n)| n > 0)
+                    .map(|(col, &n)| (col.name().to_string(), n))
+                    .collect();
+
+                let mut table_notes = Vec::new();
+                if let Some(limit) = set.truncated_by {
+                    let reason = match limit {
+                        StopReason::MaxBytes => "the output reached max_bytes",
+                        StopReason::MaxRows => "more were available",
+                    };
+                    let shown = match (skipped, set.rows.len()) {
+                        (_, 0) => "no rows".to_string(),
+                        (0, n) => format!("the first {} rows", n),
+                        (skipped, n) => format!("rows {}–{}", skipped + 1, skipped + n),
+                    };
+                    let cancelled = if batch.cancelled && i + 1 == batch.sets.len() {
+                        ", so the rest of the batch was cancelled"
+                    } else {
+                        ""
+                    };
+                    table_notes.push(format!("Showing {}; {}{}", shown, reason, cancelled));
+                }
+                if let (false, Some(max)) = (cut_cells.is_empty(), max) {
+                    let cut: Vec<String> = cut_cells
+                        .iter()
+                        .map(|(name, n)| format!("{} ({})", name, n))
+                        .collect();
+                    table_notes.push(format!(
+                        "Cells cut at {} characters or binary summarized: {}",
+                        max,
+                        cut.join(", ")
+                    ));
+                }
+
+                tables.push(Table {
+                    title: format!("Result set {}", i + 1),
+                    columns,
+                    rows,
+                    skipped,
+                    truncated: set.truncated_by.is_some(),
+                    cut_cells,
+                    notes: table_notes,
+                });
+            }
+            if batch.timed_out {
+                notes
+                    .push("Query timed out and was cancelled; showing partial results".to_string());
+            }
+        }
+
+        let mut fields = Map::new();
+        fields.insert(
+            "timed_out".to_string(),
+            Value::from(batches.iter().any(|b| b.timed_out)),
+        );
+        fields.insert(
+            "stopped_by".to_string(),
+            json!(batches.iter().find_map(|b| b.stopped_by)),
+        );
+        fields.insert(
+            "max_cell_chars".to_string(),
+            Value::from(batches.first().and_then(|b| b.max_cell_chars)),
+        );
+        let elapsed: Duration = batches.iter().map(|b| b.elapsed).sum();
+        fields.insert(
+            "elapsed_ms".to_string(),
+            Value::from(elapsed.as_millis() as u64),
+        );
+
+        Self {
+            tables,
+            fields,
+            notes,
+        }
+    }
+
+    /// Structured content: every result set with typed columns and rows,
+    /// plus `fields`. Matches `tools::result_sets_schema`.
+    pub fn to_json(&self) -> Value {
+        let mut obj = Map::new();
+        let sets: Vec<Value> = self.tables.iter().map(Table::to_json).collect();
+        obj.insert("result_sets".to_string(), Value::from(sets));
+        obj.extend(self.fields.clone());
+        Value::Object(obj)
+    }
+
+    /// Render the text content in `format`, with `to_json` as the
+    /// structured content.
+    pub fn into_output(self, format: Format) -> ToolOutput {
+        ToolOutput {
+            content: format.renderer().render(&self),
+            structured: self.to_json(),
+        }
+    }
+}
+
+impl Table {
+    fn to_json(&self) -> Value {
+        let columns: Vec<Value> = self
+            .columns
+            .iter()
+            .map(|c| json!({ "name": c.name, "type": c.sql_type }))
+            .collect();
+        let rows: Vec<Value> = self
+            .rows
+            .iter()
+            .map(|row| row.iter().map(|cell| cell.value.clone()).collect())
+            .collect();
+        let cut_cells: Vec<Value> = self
+            .cut_cells
+            .iter()
+            .map(|(name, n)| json!({ "column": name, "cells": n }))
+            .collect();
+        json!({
+            "columns": columns,
+            "rows": rows,
+            "truncated": self.truncated,
+            "cut_cells": cut_cells
+        })
+    }
+}
+
+/// Markdown tables. A single set is rendered as a bare table; several get
+/// their own headings and a count.
+pub struct Markdown;
+
+impl Renderer for Markdown {
+    fn render(&self, results: &Results) -> Vec<String> {
+        let tables = &results.tables;
+        let mut md = match tables.len() {
+            0 => "(no results)".to_string(),
+            1 => markdown_table(&tables[0]),
+            n => {
+                let mut md = String::new();
+                for table in tables {
+                    md.push_str(&format!("### {}\n\n", table.title));
+                    md.push_str(&markdown_table(table));
+                    md.push_str("\n\n");
+                }
+                md.push_str(&format!("_{} result sets returned_", n));
+                md
+            }
+        };
+        for note in &results.notes {
+            md.push_str(&format!("\n\n_{}_", escape_markdown(note)));
+        }
+        vec![md]
+    }
+}
+
+fn markdown_table(table: &Table) -> String {
+    let mut md = if table.rows.is_empty() && !table.truncated {
+        "(no results)".to_string()
+    } else if table.columns.is_empty() {
+        "(no columns returned)".to_string()
+    } else {
+        let mut md = String::from("|");
+        for col in &table.columns {
+            md.push_str(&format!(" {} |", escape_markdown(&col.name)));
+        }
+        md.push_str("\n|");
+        for _ in &table.columns {
+            md.push_str(" --- |");
+        }
+        for row in &table.rows {
+            md.push_str("\n|");
+            for cell in row {
+                let val = cell
+                    .text
+                    .as_deref()
+                    .map_or_else(|| "NULL".to_string(), escape_markdown);
+                md.push_str(&format!(" {} |", val));
+            }
+        }
+        md
+    };
+    for note in &table.notes {
+        md.push_str(&format!("\n\n_{}_", escape_markdown(note)));
+    }
+    md
+}
+
+/// Keep a value inside its markdown table cell: escape `|` and turn line
+/// breaks into `<br>`.
+fn escape_markdown(text: &str) -> String {
+    text.replace('|', "\\|")
+        .replace("\r\n", "<br>")
+        .replace(['\n', '\r'], "<br>")
+}
+
+/// The structured content as compact JSON, plus its `notes`.
+pub struct Json;
+
+impl Renderer for Json {
+    fn render(&self, results: &Results) -> Vec<String> {
+        let mut value = results.to_json();
+        value["notes"] = Value::from(results.notes.clone());
+        vec![value.to_string()]
+    }
+}
+
+/// One JSON object per row, keyed by column name in column order; see
+/// [`column_keys`].
+pub struct Jsonl;
+
+impl Renderer for Jsonl {
+    fn render(&self, results: &Results) -> Vec<String> {
+        data_blocks(results, |table| {
+            let keys: Vec<Value> = column_keys(table.columns.iter().map(|c| c.name.as_str()))
+                .into_iter()
+                .map(Value::from)
+                .collect();
+            let lines: Vec<String> = table
+                .rows
+                .iter()
+                .map(|row| {
+                    let fields: Vec<String> = keys
+                        .iter()
+                        .zip(row)
+                        .map(|(key, cell)| format!("{}:{}", key, cell.value))
+                        .collect();
+                    format!("{{{}}}", fields.join(","))
+                })
+                .collect();
+            lines.join("\n")
+        })
+    }
+}
+
+/// Object keys for a row's columns. Unnamed columns become `column_N`, N
+/// being the column's position, and repeated names get `_2`, `_3`, ..., so
+/// every value keeps a key of its own.
+pub fn column_keys<'a>(names: impl IntoIterator<Item = &'a str>) -> Vec<String> {
+    let names: Vec<&str> = names.into_iter().collect();
+    let mut keys: Vec<String> = Vec::with_capacity(names.len());
+    for (i, &name) in names.iter().enumerate() {
+        let base = match name {
+            "" => format!("column_{}", i + 1),
+            name => name.to_string(),
+        };
+        let mut key = base.clone();
+        let mut n = 1;
+        while keys.contains(&key) || (key != name && names.contains(&key.as_str())) {
+            n += 1;
+            key = format!("{}_{}", base, n);
+        }
+        keys.push(key);
+    }
+    keys
+}
+
+/// RFC 4180 CSV with a header row. NULL is an empty field.
+pub struct Csv;
+
+impl Renderer for Csv {
+    fn render(&self, results: &Results) -> Vec<String> {
+        data_blocks(results, |table| {
+            let header: Vec<String> = table.columns.iter().map(|c| csv_field(&c.name)).collect();
+            let mut lines = vec![header.join(",")];
+            for row in &table.rows {
+                let fields: Vec<String> = row
+                    .iter()
+                    .map(|cell| csv_field(cell.text.as_deref().unwrap_or_default()))
+                    .collect();
+                lines.push(fields.join(","));
+            }
+            lines.join("\n")
+        })
+    }
+}
+
+/// Layout shared by the data formats: one content block per result set
+/// holding only its data, so each parses on its own, then one block with
+/// the notes, each naming the set it is about.
+fn data_blocks(results: &Results, render_table: impl Fn(&Table) -> String) -> Vec<String> {
+    let mut blocks: Vec<String> = results.tables.iter().map(render_table).collect();
+    let mut notes = Vec::new();
+    match results.tables.len() {
+        0 => notes.push("No result sets returned".to_string()),
+        1 => {}
+        n => notes.push(format!("{} result sets returned, one per content block", n)),
+    }
+    for table in &results.tables {
+        for note in &table.notes {
+            if results.tables.len() > 1 {
+                notes.push(format!("{}: {}", table.title, note));
+            } else {
+                notes.push(note.clone());
+            }
+        }
+    }
+    notes.extend(results.notes.iter().cloned());
+    if !notes.is_empty() {
+        blocks.push(notes.join("\n"));
+    }
+    blocks
+}
+
+/// Quote a field if it holds a separator, quote or line break.
+pub fn csv_field(text: &str) -> String {
+    if text.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", text.replace('"', "\"\""))
+    } else {
+        text.to_string()
+    }
+}
+
+/// Each row as a record of `column | value` lines, like psql's `\x`.
+pub struct Vertical;
+
+impl Renderer for Vertical {
+    fn render(&self, results: &Results) -> Vec<String> {
+        render_lines(results, |table, out| {
+            if table.rows.is_empty() {
+                out.push("(no rows)".to_string());
+                return;
+            }
+            let width = table
+                .columns
+                .iter()
+                .map(|c| c.name.chars().count())
+                .max()
+                .unwrap_or_default();
+            let records: Vec<Vec<String>> = table
+                .rows
+                .iter()
+                .map(|row| {
+                    let mut lines = Vec::new();
+                    for (col, cell) in table.columns.iter().zip(row) {
+                        let text = cell.text.as_deref().unwrap_or("NULL");
+                        let mut name = col.name.as_str();
+                        for line in text.lines().chain(text.is_empty().then_some("")) {
+                            lines.push(format!("{:<width$} | {}", name, line));
+                            name = "";
+                        }
+                    }
+                    lines
+                })
+                .collect();
+            let len = records
+                .iter()
+                .flatten()
+                .map(|l| l.chars().count())
+                .max()
+                .unwrap_or_default();
+            for (i, lines) in records.into_iter().enumerate() {
+                let header = format!("-[ RECORD {} ]", table.skipped + i + 1);
+                let dashes = len.saturating_sub(header.chars().count());
+                out.push(format!("{}{}", header, "-".repeat(dashes)));
+                out.extend(lines);
+            }
+        })
+    }
+}
+
+/// Layout of the vertical format: each table's lines, with a `# title`
+/// line when there are several tables and the notes as `# ` lines, blocks
+/// separated by a blank line.
+fn render_lines(results: &Results, table_lines: impl Fn(&Table, &mut Vec<String>)) -> Vec<String> {
+    let mut blocks = Vec::new();
+    for table in &results.tables {
+        let mut lines = Vec::new();
+        if results.tables.len() > 1 {
+            lines.push(format!("# {}", table.title));
+        }
+        table_lines(table, &mut lines);
+        lines.extend(table.notes.iter().map(|note| format!("# {}", note)));
+        blocks.push(lines.join("\n"));
+    }
+    if !results.notes.is_empty() {
+        let notes: Vec<String> = results.notes.iter().map(|n| format!("# {}", n)).collect();
+        blocks.push(notes.join("\n"));
+    }
+    vec![blocks.join("\n\n")]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tools::schema::{ResultSet, StopReason};
+    use claw::{Column, ColumnType};
+
+    fn set(truncated_by: Option<StopReason>) -> ResultSet {
+        ResultSet {
+            columns: vec![Column::new("n".to_string(), ColumnType::Int4)],
+            rows: Vec::new(),
+            truncated_by,
+            cut_cells: vec![0],
+        }
+    }
+
+    fn batch(sets: Vec<ResultSet>, cancelled: bool) -> Batch {
+        let stopped_by = sets.iter().find_map(|s| s.truncated_by);
+        Batch {
+            sets,
+            timed_out: false,
+            stopped_by,
+            cancelled,
+            max_cell_chars: None,
+            first_row: 0,
+            elapsed: Duration::ZERO,
+        }
+    }
+
+    #[test]
+    fn each_cut_set_names_its_limit() {
+        let batch = batch(
+            vec![
+                set(Some(StopReason::MaxRows)),
+                set(None),
+                set(Some(StopReason::MaxBytes)),
+            ],
+            false,
+        );
+        let results = Results::from_batches(&[&batch]);
+        assert_eq!(
+            results.tables[0].notes,
+            ["Showing no rows; more were available"]
+        );
+        assert!(results.tables[1].notes.is_empty());
+        assert_eq!(
+            results.tables[2].notes,
+            ["Showing no rows; the output reached max_bytes"]
+        );
+        assert_eq!(results.fields["stopped_by"], "max_rows");
+    }
+
+    #[test]
+    fn a_cancelled_batch_says_so_on_its_last_set() {
+        let batch = batch(vec![set(None), set(Some(StopReason::MaxRows))], true);
+        let results = Results::from_batches(&[&batch]);
+        assert_eq!(
+            results.tables[1].notes,
+            ["Showing no rows; more were available, so the rest of the batch was cancelled"]
+        );
+    }
+
+    fn table(columns: &[&str], rows: &[&[Option<&str>]], notes: &[&str]) -> Table {
+        Table {
+            title: "Result set 1".to_string(),
+            columns: columns
+                .iter()
+                .map(|name| TableColumn {
+                    name: name.to_string(),
+                    sql_type: "nvarchar",
+                })
+                .collect(),
+            rows: rows
+                .iter()
+                .map(|row| {
+                    row.iter()
+                        .map(|text| Cell::new(text.map(String::from), Value::from(*text)))
+                        .collect()
+                })
+                .collect(),
+            skipped: 0,
+            truncated: false,
+            cut_cells: Vec::new(),
+            notes: notes.iter().map(|n| n.to_string()).collect(),
+        }
+    }
+
+    fn results(tables: Vec<Table>, notes: &[&str]) -> Results {
+        Results {
+            tables,
+            fields: Map::new(),
+            notes: notes.iter().map(|n| n.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn csv_keeps_notes_out_of_the_data() {
+        let results = results(
+            vec![table(
+                &["id", "note"],
+                &[&[Some("1"), Some("# not a comment")], &[Some("2"), None]],
+                &["Showing the first 2 rows; more were available"],
+            )],
+            &["Database: db"],
+        );
+        assert_eq!(
+            Csv.render(&results),
+            [
+                "id,note\n1,# not a comment\n2,",
+                "Showing the first 2 rows; more were available\nDatabase: db",
+            ]
+        );
+    }
+
+    #[test]
+    fn each_result_set_gets_its_own_block() {
+        let mut second = table(&["b"], &[&[Some("x,y")]], &["Cut"]);
+        second.title = "Result set 2".to_string();
+        let results = results(vec![table(&["a"], &[], &[]), second], &[]);
+        assert_eq!(
+            Csv.render(&results),
+            [
+                "a",
+                "b\n\"x,y\"",
+                "2 result sets returned, one per content block\nResult set 2: Cut",
+            ]
+        );
+        assert_eq!(
+            Csv.render(&Results::from_batches(&[]))[0],
+            "No result sets returned"
+        );
+    }
+
+    #[test]
+    fn jsonl_keys_are_unique() {
+        let results = results(
+            vec![table(
+                &["a", "a", "", "a_2"],
+                &[&[Some("1"), Some("2"), None, Some("3")]],
+                &[],
+            )],
+            &[],
+        );
+        assert_eq!(
+            Jsonl.render(&results),
+            [r#"{"a":"1","a_3":"2","column_3":null,"a_2":"3"}"#]
+        );
+    }
+
+    #[test]
+    fn markdown_escapes_cells() {
+        let results = results(
+            vec![table(&["a|b"], &[&[Some("x\ny")], &[None]], &["Note"])],
+            &[],
+        );
+        assert_eq!(
+            Markdown.render(&results),
+            ["| a\\|b |\n| --- |\n| x<br>y |\n| NULL |\n\n_Note_"]
+        );
+    }
+
+    #[test]
+    fn vertical_numbers_records_from_the_page_start() {
+        let mut table = table(&["id", "name"], &[&[Some("1"), Some("ab")]], &[]);
+        table.skipped = 10;
+        assert_eq!(
+            Vertical.render(&results(vec![table], &[])),
+            ["-[ RECORD 11 ]\nid   | 1\nname | ab"]
+        );
+    }
+}
@@ -1,90 +1,330 @@
+use std::future::Future;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use claw::{Column, QueryItem, Row};
+use futures_util::TryStreamExt;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use tokio::time::Instant;
+use tracing::warn;
+
 use crate::connection::Connection;
+use crate::tools::render::{Format, Markdown, Renderer, Results};
+use crate::tools::value::render_limited;
+use crate::tools::{DatabaseArgs, FormatArgs, Tool, ToolOutput};
+use crate::Args;
 
-/// Execute a SQL query and return results as a markdown table string.
+/// A limit that cut a result set short.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StopReason {
+    MaxRows,
+    MaxBytes,
+}
+
+/// How much of a batch to return. `None` means no limit.
+#[derive(Clone, Copy, Default)]
+pub struct Limits {
+    /// Rows the batch's `OFFSET` skips, which an earlier page returned.
+    /// Only numbers the rows shown.
+    pub first_row: usize,
+    /// Rows per result set.
+    pub max_rows: Option<usize>,
+    /// Bytes of cell text across the whole batch.
+    pub max_bytes: Option<usize>,
+    /// Characters per cell; longer values are cut with a marker.
+    pub max_cell_chars: Option<usize>,
+    /// Cancel the rest of the batch once a limit cuts a result set, so the
+    /// rows past the limit are never sent. Otherwise the batch is read to
+    /// the end to collect the later result sets.
+    pub cancel_at_limit: bool,
+}
+
+impl Limits {
+    /// `max_rows` plus the server-wide `--max-bytes` and `--max-cell-chars`,
+    /// cancelling the batch at the first cut.
+    pub fn from_args(args: &Args, max_rows: Option<usize>) -> Self {
+        Self {
+            first_row: 0,
+            max_rows,
+            max_bytes: Some(args.max_bytes).filter(|&n| n > 0),
+            max_cell_chars: Some(args.max_cell_chars).filter(|&n| n > 0),
+            cancel_at_limit: true,
+        }
+    }
+}
+
+/// A single result set collected from a batch.
+pub struct ResultSet {
+    pub columns: Vec<Column>,
+    pub rows: Vec<Row>,
+    /// The limit that kept some of the set's rows from being shown.
+    pub truncated_by: Option<StopReason>,
+    /// Per column, how many shown cells were cut by `max_cell_chars`.
+    pub cut_cells: Vec<usize>,
+}
+
+/// Every result set collected from a batch.
+pub struct Batch {
+    pub sets: Vec<ResultSet>,
+    /// The batch hit its timeout and was cancelled; `sets` holds what
+    /// arrived before that.
+    pub timed_out: bool,
+    /// The first limit that cut a result set.
+    pub stopped_by: Option<StopReason>,
+    /// A limit cancelled the rest of the batch (see
+    /// [`Limits::cancel_at_limit`]); the last set in `sets` is the one it cut.
+    pub cancelled: bool,
+    /// Cell limit the batch was read with, applied again when rendering.
+    pub max_cell_chars: Option<usize>,
+    /// Rows before the first one in `sets[0]`, skipped by an `OFFSET`.
+    pub first_row: usize,
+    /// Time from sending the batch to its last result.
+    pub elapsed: Duration,
+}
+
+/// Execute a SQL batch and return every result set as a markdown table string.
+///
+/// `max_rows` applies to each result set independently; see
+/// [`fetch_result_sets`]. The batch is bounded by the global `--query-timeout`.
 pub async fn query_to_markdown(
     conn: &mut Connection,
     sql: &str,
+    params: &[&dyn claw::IntoSql],
     max_rows: Option<usize>,
 ) -> Result<String, String> {
+    let timeout = conn.query_timeout();
+    let limits = Limits::from_args(conn.args(), max_rows);
+    let batch = fetch_result_sets(conn, sql, params, limits, timeout).await?;
+    Ok(Markdown.render(&Results::from_batches(&[&batch])).concat())
+}
+
+/// Like [`query_to_markdown`], but renders the text in `format` and also
+/// returns the result sets as structured content.
+pub async fn query_to_output(
+    conn: &mut Connection,
+    sql: &str,
+    params: &[&dyn claw::IntoSql],
+    max_rows: Option<usize>,
+    format: Format,
+) -> Result<ToolOutput, String> {
+    let limits = Limits::from_args(conn.args(), max_rows);
+    query_to_output_with(conn, sql, params, limits, format).await
+}
+
+/// [`query_to_output`] with explicit limits. Catalog tools pass
+/// `Limits::default()`: their output has no cursor to fetch the rest, so it
+/// is never cut.
+pub async fn query_to_output_with(
+    conn: &mut Connection,
+    sql: &str,
+    params: &[&dyn claw::IntoSql],
+    limits: Limits,
+    format: Format,
+) -> Result<ToolOutput, String> {
+    let timeout = conn.query_timeout();
+    let batch = fetch_result_sets(conn, sql, params, limits, timeout).await?;
+    Ok(Results::from_batches(&[&batch]).into_output(format))
+}
+
+/// Execute a SQL batch and collect its result sets within `limits`.
+/// `params` are bound as `@P1..@Pn`.
+///
+/// Rows are consumed as they stream in. Once a result set has more than
+/// `max_rows` rows, or the next row would take the shown cells past
+/// `max_bytes`, that set is marked as truncated and, with
+/// `limits.cancel_at_limit`, the rest of the batch is cancelled. Without it,
+/// the set's remaining rows are read but not kept; once `max_bytes` is
+/// reached, so are the rows of every later set. Later sets still get their
+/// columns, so each statement of the batch keeps its own result set. The
+/// first row of a batch is shown
+/// whatever its size, so a page always makes progress.
+///
+/// If `timeout` elapses the batch is cancelled on the server and whatever
+/// was streamed so far is returned; it is an error only if nothing arrived.
+pub async fn fetch_result_sets(
+    conn: &mut Connection,
+    sql: &str,
+    params: &[&dyn claw::IntoSql],
+    limits: Limits,
+    timeout: Option<Duration>,
+) -> Result<Batch, String> {
+    let started = Instant::now();
+    let deadline = timeout.map(|t| started + t);
+    let progress = conn.progress();
     let client = match conn.get_client().await {
         Ok(c) => c,
-        Err(_) => conn.reconnect().await?,
+        Err(e) => {
+            warn!("Reconnecting after connection error: {}", e);
+            conn.reconnect().await?
+        }
     };
 
-    let params: Vec<&dyn claw::IntoSql> = vec![];
-    let stream = client
-        .execute(sql, &params)
-        .await
-        .map_err(|e| format!("Query error: {}", e))?;
+    let max_rows = limits.max_rows.unwrap_or(usize::MAX);
+    let max_bytes = limits.max_bytes.unwrap_or(usize::MAX);
+    let mut bytes = 0;
+    let mut bytes_full = false;
+    let mut sets: Vec<ResultSet> = Vec::new();
+    let mut seen_sets = 0;
+    let mut timed_out = false;
+    let mut stopped_by = None;
+    let mut cancelled = false;
 
-    let rows = stream
-        .into_first_result()
-        .await
-        .map_err(|e| format!("Result error: {}", e))?;
-
-    if rows.is_empty() {
-        return Ok("(no results)".to_string());
-    }
+    match with_deadline(deadline, client.execute(sql, params)).await {
+        Some(result) => {
+            let mut stream = result.map_err(|e| format!("Query error: {}", e))?;
 
-    // Get column names from first row
-    let columns: Vec<String> = rows[0]
-        .columns()
-        .iter()
-        .map(|c| c.name().to_string())
-        .collect();
+            loop {
+                let item = match with_deadline(deadline, stream.try_next()).await {
+                    Some(item) => item.map_err(|e| format!("Result error: {}", e))?,
+                    None => {
+                        timed_out = true;
+                        break;
+                    }
+                };
 
-    if columns.is_empty() {
-        return Ok("(no columns returned)".to_string());
+                match item {
+                    Some(QueryItem::Metadata(meta)) => {
+                        seen_sets += 1;
+                        sets.push(ResultSet {
+                            columns: meta.columns().to_vec(),
+                            rows: Vec::new(),
+                            truncated_by: None,
+                            cut_cells: vec![0; meta.columns().len()],
+                        });
+                    }
+                    Some(QueryItem::Row(row)) => {
+                        if let Some(progress) = &progress {
+                            progress.row(seen_sets);
+                        }
+                        let set = match sets.last_mut() {
+                            Some(set) if set.truncated_by.is_none() => set,
+                            // Already cut: read past the rest of the set.
+                            _ => continue,
+                        };
+                        let cut_by = if bytes_full {
+                            Some(StopReason::MaxBytes)
+                        } else if set.rows.len() >= max_rows {
+                            Some(StopReason::MaxRows)
+                        } else {
+                            let mut row_bytes = 0;
+                            let mut cut = Vec::new();
+                            for (i, (col, data)) in row.cells().enumerate() {
+                                let (text, was_cut) =
+                                    render_limited(col.column_type(), data, limits.max_cell_chars);
+                                row_bytes += text.map_or(4, |t| t.len());
+                                if was_cut {
+                                    cut.push(i);
+                                }
+                            }
+                            if bytes > 0 && bytes + row_bytes > max_bytes {
+                                bytes_full = true;
+                                Some(StopReason::MaxBytes)
+                            } else {
+                                bytes += row_bytes;
+                                for i in cut {
+                                    set.cut_cells[i] += 1;
+                                }
+                                set.rows.push(row);
+                                None
+                            }
+                        };
+                        if let Some(limit) = cut_by {
+                            set.truncated_by = Some(limit);
+                            stopped_by.get_or_insert(limit);
+                            if limits.cancel_at_limit {
+                                cancelled = true;
+                                break;
+                            }
+                        }
+                    }
+                    None => break,
+                }
+            }
+        }
+        None => timed_out = true,
     }
 
-    let max = max_rows.unwrap_or(rows.len());
-    let display_rows = &rows[..rows.len().min(max)];
+    if timed_out || cancelled {
+        conn.cancel();
+    }
+    if timed_out && sets.is_empty() {
+        return Err(format!(
+            "Query timed out after {}s and was cancelled",
+            timeout.unwrap_or_default().as_secs()
+        ));
+    }
 
-    let mut md = String::new();
+    Ok(Batch {
+        sets,
+        timed_out,
+        stopped_by,
+        cancelled,
+        max_cell_chars: limits.max_cell_chars,
+        first_row: limits.first_row,
+        elapsed: started.elapsed(),
+    })
+}
 
-    // Header
-    md.push('|');
-    for col in &columns {
-        md.push_str(&format!(" {} |", col));
+/// Await `fut`, giving up at `deadline` if one is set.
+pub async fn with_deadline<F: Future>(deadline: Option<Instant>, fut: F) -> Option<F::Output> {
+    match deadline {
+        Some(deadline) => tokio::time::timeout_at(deadline, fut).await.ok(),
+        None => Some(fut.await),
     }
-    md.push('\n');
+}
 
-    // Separator
-    md.push('|');
-    for _ in &columns {
-        md.push_str(" --- |");
-    }
-    md.push('\n');
-
-    // Rows
-    for row in display_rows {
-        md.push('|');
-        for (i, _col) in columns.iter().enumerate() {
-            let val: Option<&str> = row.try_get::<&str, _>(i).ok().flatten();
-            md.push_str(&format!(" {} |", val.unwrap_or("NULL")));
+/// Build a batch that runs `body` inside the database bound as `@P1`.
+///
+/// The name is quoted server-side with `QUOTENAME` instead of being spliced
+/// into the SQL here. `decls` declares the parameters `body` uses, which are
+/// bound from `@P2` onwards in order.
+pub fn in_database(body: &str, decls: &[&str]) -> String {
+    let mut exec = String::from("EXEC sp_executesql @prowl_sql");
+    if !decls.is_empty() {
+        exec.push_str(&format!(", N'{}'", decls.join(", ")));
+        for (i, decl) in decls.iter().enumerate() {
+            let name = decl.split_whitespace().next().unwrap_or_default();
+            exec.push_str(&format!(", {} = @P{}", name, i + 2));
         }
-        md.push('\n');
     }
 
-    if rows.len() > max {
-        md.push_str(&format!("\n_Showing {} of {} rows_\n", max, rows.len()));
-    }
-
-    Ok(md)
+    format!(
+        r#"IF DB_ID(@P1) IS NULL
+    RAISERROR('Database %s does not exist', 16, 1, @P1);
+ELSE
+BEGIN
+    DECLARE @prowl_sql nvarchar(max) = N'USE ' + QUOTENAME(@P1) + N'; ' + N'{}';
+    {};
+END"#,
+        body.replace('\'', "''"),
+        exec
+    )
 }
 
-pub async fn list_databases(conn: &mut Connection) -> Result<String, String> {
-    query_to_markdown(conn, "SELECT name FROM sys.databases ORDER BY name", None).await
+pub async fn list_databases(conn: &mut Connection, format: Format) -> Result<ToolOutput, String> {
+    query_to_output_with(
+        conn,
+        "SELECT name FROM sys.databases ORDER BY name",
+        &[],
+        Limits::default(),
+        format,
+    )
+    .await
 }
 
-pub async fn list_tables(conn: &mut Connection, database: &str) -> Result<String, String> {
-    let db = database.replace('\'', "''").replace(']', "]]");
-    let sql = format!(
-        "USE [{}]; SELECT TABLE_SCHEMA, TABLE_NAME, TABLE_TYPE \
+pub async fn list_tables(
+    conn: &mut Connection,
+    database: &str,
+    format: Format,
+) -> Result<ToolOutput, String> {
+    let sql = in_database(
+        "SELECT TABLE_SCHEMA, TABLE_NAME, TABLE_TYPE \
          FROM INFORMATION_SCHEMA.TABLES ORDER BY TABLE_SCHEMA, TABLE_NAME",
-        db
+        &[],
     );
-    query_to_markdown(conn, &sql, None).await
+    query_to_output_with(conn, &sql, &[&database], Limits::default(), format).await
 }
 
 pub async fn describe_table(
@@ -92,15 +332,10 @@ pub async fn describe_table(
     database: &str,
     schema: &str,
     table: &str,
-) -> Result<String, String> {
-    let db = database.replace('\'', "''").replace(']', "]]");
-    let sch = schema.replace('\'', "''");
-    let tbl = table.replace('\'', "''");
-
-    let sql = format!(
-        r#"USE [{db}];
-
-SELECT
+    format: Format,
+) -> Result<ToolOutput, String> {
+    let sql = in_database(
+        r#"SELECT
     c.COLUMN_NAME,
     c.DATA_TYPE,
     c.CHARACTER_MAXIMUM_LENGTH,
@@ -119,19 +354,18 @@ LEFT JOIN (
 ) pk ON c.TABLE_SCHEMA = pk.TABLE_SCHEMA
     AND c.TABLE_NAME = pk.TABLE_NAME
     AND c.COLUMN_NAME = pk.COLUMN_NAME
-WHERE c.TABLE_SCHEMA = '{sch}' AND c.TABLE_NAME = '{tbl}'
+WHERE c.TABLE_SCHEMA = @schema AND c.TABLE_NAME = @table
 ORDER BY c.ORDINAL_POSITION"#,
-        db = db,
-        sch = sch,
-        tbl = tbl
+        &["@schema sysname", "@table sysname"],
     );
 
-    let columns_md = query_to_markdown(conn, &sql, None).await?;
+    let timeout = conn.query_timeout();
+    let limits = Limits::default();
+    let params: [&dyn claw::IntoSql; 3] = [&database, &schema, &table];
+    let columns = fetch_result_sets(conn, &sql, &params, limits, timeout).await?;
 
-    let fk_sql = format!(
-        r#"USE [{db}];
-
-SELECT
+    let fk_sql = in_database(
+        r#"SELECT
     fk.name AS FK_NAME,
     COL_NAME(fkc.parent_object_id, fkc.parent_column_id) AS COLUMN_NAME,
     OBJECT_SCHEMA_NAME(fkc.referenced_object_id) AS REF_SCHEMA,
@@ -139,17 +373,84 @@ SELECT
     COL_NAME(fkc.referenced_object_id, fkc.referenced_column_id) AS REF_COLUMN
 FROM sys.foreign_keys fk
 JOIN sys.foreign_key_columns fkc ON fk.object_id = fkc.constraint_object_id
-WHERE fk.parent_object_id = OBJECT_ID('{sch}.{tbl}')
+WHERE fk.parent_object_id = OBJECT_ID(QUOTENAME(@schema) + '.' + QUOTENAME(@table))
 ORDER BY fk.name"#,
-        db = db,
-        sch = sch,
-        tbl = tbl
+        &["@schema sysname", "@table sysname"],
     );
 
-    let fk_md = query_to_markdown(conn, &fk_sql, None).await?;
+    let fks = fetch_result_sets(conn, &fk_sql, &params, limits, timeout).await?;
+
+    let mut results = Results::from_batches(&[&columns, &fks]);
+    for (table, title) in results.tables.iter_mut().zip(["Columns", "Foreign Keys"]) {
+        table.title = title.to_string();
+    }
+    Ok(results.into_output(format))
+}
+
+pub struct ListDatabases;
+
+#[async_trait]
+impl Tool for ListDatabases {
+    type Args = FormatArgs;
 
-    Ok(format!(
-        "## Columns\n\n{}\n\n## Foreign Keys\n\n{}",
-        columns_md, fk_md
-    ))
+    const NAME: &'static str = "list_databases";
+    const TITLE: &'static str = "List databases";
+    const DESCRIPTION: &'static str = "List all databases on the SQL Server instance";
+
+    async fn call(&self, args: FormatArgs, conn: &mut Connection) -> Result<ToolOutput, String> {
+        list_databases(conn, args.format).await
+    }
+}
+
+pub struct ListTables;
+
+#[async_trait]
+impl Tool for ListTables {
+    type Args = DatabaseArgs;
+
+    const NAME: &'static str = "list_tables";
+    const TITLE: &'static str = "List tables";
+    const DESCRIPTION: &'static str = "List all tables in a database";
+
+    async fn call(&self, args: DatabaseArgs, conn: &mut Connection) -> Result<ToolOutput, String> {
+        list_tables(conn, &args.database, args.format).await
+    }
+}
+
+pub struct DescribeTable;
+
+#[derive(Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct DescribeTableArgs {
+    /// Database name
+    database: String,
+    /// Schema name
+    #[serde(default = "default_schema")]
+    schema: String,
+    /// Table name
+    table: String,
+    #[serde(default)]
+    format: Format,
+}
+
+fn default_schema() -> String {
+    "dbo".to_string()
+}
+
+#[async_trait]
+impl Tool for DescribeTable {
+    type Args = DescribeTableArgs;
+
+    const NAME: &'static str = "describe_table";
+    const TITLE: &'static str = "Describe table";
+    const DESCRIPTION: &'static str =
+        "Describe a table's columns, types, nullability, primary keys, and foreign keys";
+
+    async fn call(
+        &self,
+        args: DescribeTableArgs,
+        conn: &mut Connection,
+    ) -> Result<ToolOutput, String> {
+        describe_table(conn, &args.database, &args.schema, &args.table, args.format).await
+    }
 }
@@ -0,0 +1,663 @@
+//! A small T-SQL lexer and statement classifier used to decide whether a
+//! batch is read-only before it is sent to the server.
+
+/// A lexical token. Comments and whitespace are dropped.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Token {
+    /// Keyword or regular identifier, upper-cased.
+    Word(String),
+    /// `[bracketed]` or `"quoted"` identifier, unescaped.
+    Quoted(String),
+    /// `'string'` or `N'string'` literal.
+    Str,
+    Number,
+    /// `@local` or `@@global` variable.
+    Variable,
+    Symbol(char),
+}
+
+/// Split a batch into tokens, skipping whitespace and comments.
+pub fn tokenize(sql: &str) -> Result<Vec<Token>, String> {
+    Ok(lex(sql)?.into_iter().map(|(token, _)| token).collect())
+}
+
+/// [`tokenize`], with the char index after each token.
+fn lex(sql: &str) -> Result<Vec<(Token, usize)>, String> {
+    let chars: Vec<char> = sql.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        let next = chars.get(i + 1).copied();
+
+        if c.is_whitespace() {
+            i += 1;
+        } else if c == '-' && next == Some('-') {
+            while i < chars.len() && chars[i] != '\n' {
+                i += 1;
+            }
+        } else if c == '/' && next == Some('*') {
+            // Block comments nest in T-SQL.
+            let mut depth = 0;
+            loop {
+                match (chars.get(i), chars.get(i + 1)) {
+                    (Some('/'), Some('*')) => {
+                        depth += 1;
+                        i += 2;
+                    }
+                    (Some('*'), Some('/')) => {
+                        depth -= 1;
+                        i += 2;
+                        if depth == 0 {
+                            break;
+                        }
+                    }
+                    (Some(_), _) => i += 1,
+                    (None, _) => return Err("unterminated block comment".to_string()),
+                }
+            }
+        } else if c == '\'' || ((c == 'N' || c == 'n') && next == Some('\'')) {
+            if c != '\'' {
+                i += 1;
+            }
+            let (_, end) = read_delimited(&chars, i, '\'')
+                .ok_or_else(|| "unterminated string literal".to_string())?;
+            i = end;
+            tokens.push((Token::Str, i));
+        } else if c == '[' {
+            let (text, end) = read_delimited(&chars, i, ']')
+                .ok_or_else(|| "unterminated bracketed identifier".to_string())?;
+            i = end;
+            tokens.push((Token::Quoted(text), i));
+        } else if c == '"' {
+            let (text, end) = read_delimited(&chars, i, '"')
+                .ok_or_else(|| "unterminated quoted identifier".to_string())?;
+            i = end;
+            tokens.push((Token::Quoted(text), i));
+        } else if c == '@' {
+            i += 1;
+            while i < chars.len() && (chars[i] == '@' || is_word_char(chars[i])) {
+                i += 1;
+            }
+            tokens.push((Token::Variable, i));
+        } else if c.is_ascii_digit() || (c == '.' && next.is_some_and(|n| n.is_ascii_digit())) {
+            let start = i;
+            i = number_end(&chars, i);
+            // `1DELETE` and `1eDELETE` are lexed differently by different
+            // readers; refuse them rather than guess.
+            if chars.get(i).is_some_and(|&c| is_word_char(c)) {
+                let text: String = chars[start..=i].iter().collect();
+                return Err(format!(
+                    "number runs into a name at `{}`; separate them with a space",
+                    text
+                ));
+            }
+            tokens.push((Token::Number, i));
+        } else if is_word_char(c) {
+            let start = i;
+            while i < chars.len() && is_word_char(chars[i]) {
+                i += 1;
+            }
+            let word: String = chars[start..i].iter().collect();
+            tokens.push((Token::Word(word.to_uppercase()), i));
+        } else {
+            i += 1;
+            tokens.push((Token::Symbol(c), i));
+        }
+    }
+
+    Ok(tokens)
+}
+
+/// Index after the numeric literal starting at `start`: digits, one `.`, an
+/// optional exponent and nothing else, or a `0x` hex body.
+fn number_end(chars: &[char], start: usize) -> usize {
+    let digits_from = |mut i: usize| {
+        while chars.get(i).is_some_and(|c| c.is_ascii_digit()) {
+            i += 1;
+        }
+        i
+    };
+
+    if chars[start] == '0' && matches!(chars.get(start + 1), Some('x') | Some('X')) {
+        let mut i = start + 2;
+        while chars.get(i).is_some_and(|c| c.is_ascii_hexdigit()) {
+            i += 1;
+        }
+        return i;
+    }
+
+    let mut i = digits_from(start);
+    if chars.get(i) == Some(&'.') {
+        i = digits_from(i + 1);
+    }
+    if matches!(chars.get(i), Some('e') | Some('E')) {
+        let sign = usize::from(matches!(chars.get(i + 1), Some('+') | Some('-')));
+        if chars.get(i + 1 + sign).is_some_and(|c| c.is_ascii_digit()) {
+            i = digits_from(i + 1 + sign);
+        }
+    }
+    i
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_' || c == '#' || c == '$'
+}
+
+/// Read a literal opened at `start` and closed by `close`, where a doubled
+/// closing character is an escape. Returns the contents and the index after it.
+fn read_delimited(chars: &[char], start: usize, close: char) -> Option<(String, usize)> {
+    let mut text = String::new();
+    let mut i = start + 1;
+    loop {
+        let c = *chars.get(i)?;
+        if c == close {
+            if chars.get(i + 1) == Some(&close) {
+                text.push(close);
+                i += 2;
+                continue;
+            }
+            return Some((text, i + 1));
+        }
+        text.push(c);
+        i += 1;
+    }
+}
+
+/// Keywords that are never allowed, with the reason reported to the caller.
+const BLOCKED: &[(&str, &str)] = &[
+    ("INSERT", "modifies data"),
+    ("UPDATE", "modifies data"),
+    ("DELETE", "modifies data"),
+    ("MERGE", "modifies data"),
+    ("TRUNCATE", "modifies data"),
+    ("UPDATETEXT", "modifies data"),
+    ("WRITETEXT", "modifies data"),
+    ("BULK", "loads data"),
+    ("CREATE", "changes the schema"),
+    ("ALTER", "changes the schema"),
+    ("DROP", "changes the schema"),
+    ("GRANT", "changes permissions"),
+    ("REVOKE", "changes permissions"),
+    ("DENY", "changes permissions"),
+    ("EXEC", "executes code that cannot be checked"),
+    ("EXECUTE", "executes code that cannot be checked"),
+    ("SP_EXECUTESQL", "executes code that cannot be checked"),
+    ("OPENROWSET", "reaches outside the server"),
+    ("OPENDATASOURCE", "reaches outside the server"),
+    ("OPENQUERY", "reaches outside the server"),
+    ("DBCC", "runs a maintenance command"),
+    ("BACKUP", "runs a maintenance command"),
+    ("RESTORE", "runs a maintenance command"),
+    ("CHECKPOINT", "runs a maintenance command"),
+    ("RECONFIGURE", "changes server configuration"),
+    ("SHUTDOWN", "stops the server"),
+    ("KILL", "terminates sessions"),
+    ("COMMIT", "controls transactions"),
+    ("ROLLBACK", "controls transactions"),
+    ("SAVE", "controls transactions"),
+    ("DISABLE", "changes the schema"),
+    ("ENABLE", "changes the schema"),
+    ("SEND", "sends a Service Broker message"),
+    ("MOVE", "moves a Service Broker conversation"),
+    ("SETUSER", "changes the security context"),
+];
+
+/// Keywords that may begin a read-only statement.
+const STATEMENT_STARTS: &[&str] = &[
+    "SELECT",
+    "WITH",
+    "DECLARE",
+    "SET",
+    "PRINT",
+    "IF",
+    "ELSE",
+    "WHILE",
+    "BEGIN",
+    "END",
+    "RETURN",
+    "BREAK",
+    "CONTINUE",
+    "USE",
+    "RAISERROR",
+    "THROW",
+    "WAITFOR",
+    "OPEN",
+    "FETCH",
+    "CLOSE",
+    "DEALLOCATE",
+];
+
+/// Session options a batch may `SET`. The pool resets them when a session is
+/// returned; any other option would carry over to the next call.
+const SETTABLE: &[&str] = &["NOCOUNT", "STATISTICS", "TRANSACTION"];
+
+/// Keywords that always begin a new statement when seen outside parentheses,
+/// even without a separating semicolon.
+const STATEMENT_BOUNDARIES: &[&str] = &[
+    "DECLARE",
+    "SET",
+    "PRINT",
+    "IF",
+    "WHILE",
+    "USE",
+    "RETURN",
+    "INSERT",
+    "UPDATE",
+    "DELETE",
+    "MERGE",
+    "TRUNCATE",
+    "CREATE",
+    "ALTER",
+    "DROP",
+    "GRANT",
+    "REVOKE",
+    "DENY",
+    "EXEC",
+    "EXECUTE",
+    "DBCC",
+    "BACKUP",
+    "RESTORE",
+    "KILL",
+    "SHUTDOWN",
+    "BULK",
+    "RECONFIGURE",
+    "COMMIT",
+    "ROLLBACK",
+    "DISABLE",
+    "ENABLE",
+    "SEND",
+    "MOVE",
+    "SETUSER",
+];
+
+/// Check that a batch only contains read-only statements.
+///
+/// Returns a human-readable reason naming the offending statement otherwise.
+pub fn check_read_only(sql: &str) -> Result<(), String> {
+    let tokens = tokenize(sql)?;
+
+    let mut statement = 1;
+    let mut depth = 0usize;
+    let mut at_start = true;
+
+    for (i, token) in tokens.iter().enumerate() {
+        let prev = i.checked_sub(1).and_then(|p| tokens.get(p));
+        let next = tokens.get(i + 1);
+
+        match token {
+            Token::Symbol(';') => {
+                statement += 1;
+                depth = 0;
+                at_start = true;
+                continue;
+            }
+            Token::Symbol('(') => depth += 1,
+            Token::Symbol(')') => depth = depth.saturating_sub(1),
+            Token::Word(word) => {
+                if depth == 0 && !at_start && STATEMENT_BOUNDARIES.contains(&word.as_str()) {
+                    statement += 1;
+                    at_start = true;
+                }
+
+                // Names after a dot are qualified identifiers, not keywords,
+                // except for procedures that execute dynamic SQL.
+                let qualified = prev == Some(&Token::Symbol('.'));
+
+                if !qualified || word == "SP_EXECUTESQL" {
+                    if let Some((_, reason)) = BLOCKED.iter().find(|(k, _)| k == word) {
+                        return Err(format!(
+                            "statement {} uses {}, which {}",
+                            statement, word, reason
+                        ));
+                    }
+                }
+
+                if word == "RECEIVE" && prev == Some(&Token::Symbol('(')) {
+                    return Err(format!(
+                        "statement {} uses WAITFOR (RECEIVE ...), which removes messages from a queue",
+                        statement
+                    ));
+                }
+
+                if word.starts_with("XP_") {
+                    return Err(format!(
+                        "statement {} calls extended procedure {}",
+                        statement, word
+                    ));
+                }
+
+                if word == "BEGIN"
+                    && matches!(
+                        next,
+                        Some(Token::Word(w)) if w == "TRAN" || w == "TRANSACTION" || w == "DISTRIBUTED"
+                    )
+                {
+                    return Err(format!(
+                        "statement {} uses BEGIN TRANSACTION, which controls transactions",
+                        statement
+                    ));
+                }
+
+                let conversation = match (word.as_str(), next) {
+                    ("BEGIN", Some(Token::Word(w))) if w == "DIALOG" || w == "CONVERSATION" => {
+                        Some("BEGIN DIALOG")
+                    }
+                    ("END", Some(Token::Word(w))) if w == "CONVERSATION" => {
+                        Some("END CONVERSATION")
+                    }
+                    _ => None,
+                };
+                if let Some(verb) = conversation {
+                    return Err(format!(
+                        "statement {} uses {}, which changes a Service Broker conversation",
+                        statement, verb
+                    ));
+                }
+
+                // INTO is only read-only when it targets variables (FETCH ... INTO @x).
+                if word == "INTO" && !matches!(next, Some(Token::Variable)) {
+                    return Err(format!(
+                        "statement {} uses SELECT ... INTO, which creates a table",
+                        statement
+                    ));
+                }
+
+                if word == "NEXT" && matches!(next, Some(Token::Word(w)) if w == "VALUE") {
+                    return Err(format!(
+                        "statement {} uses NEXT VALUE FOR, which advances a sequence",
+                        statement
+                    ));
+                }
+
+                if at_start {
+                    if !STATEMENT_STARTS.contains(&word.as_str()) {
+                        // A bare name at the start of a batch is an implicit EXEC.
+                        return Err(format!(
+                            "statement {} begins with {}, which is not a read-only statement",
+                            statement, word
+                        ));
+                    }
+                    if word == "SET" {
+                        match next {
+                            Some(Token::Variable) => {}
+                            Some(Token::Word(option)) if SETTABLE.contains(&option.as_str()) => {}
+                            Some(Token::Word(option)) => {
+                                return Err(format!(
+                                    "statement {} uses SET {}, which changes a session setting",
+                                    statement, option
+                                ));
+                            }
+                            _ => {
+                                return Err(format!(
+                                    "statement {} uses SET, which changes a session setting",
+                                    statement
+                                ));
+                            }
+                        }
+                    }
+                    at_start = false;
+                    continue;
+                }
+            }
+            Token::Quoted(name) if at_start => {
+                return Err(format!(
+                    "statement {} begins with [{}], which is not a read-only statement",
+                    statement, name
+                ));
+            }
+            _ => {}
+        }
+
+        at_start = false;
+    }
+
+    Ok(())
+}
+
+/// Check that a batch cannot leave before its last statement, so statements
+/// appended after it always run. `RETURN` exits the batch and `GOTO` can jump
+/// past the end of it.
+pub fn check_runs_to_end(sql: &str) -> Result<(), String> {
+    let tokens = tokenize(sql)?;
+    for (i, token) in tokens.iter().enumerate() {
+        let qualified = i > 0 && tokens[i - 1] == Token::Symbol('.');
+        if let Token::Word(word) = token {
+            if !qualified && (word == "RETURN" || word == "GOTO") {
+                return Err(format!("the batch uses {}, which can exit it early", word));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Words that, outside parentheses, keep a `SELECT` from simply being
+/// followed by `OFFSET ... FETCH`: it already limits its rows, or has a
+/// clause that must come after them.
+const NOT_PAGEABLE: &[&str] = &["TOP", "OFFSET", "FETCH", "FOR", "OPTION", "INTO", "COMPUTE"];
+
+/// Words that join `SELECT`s into one statement.
+const SET_OPERATORS: &[&str] = &["UNION", "ALL", "EXCEPT", "INTERSECT"];
+
+/// Rewrite a batch that is a single `SELECT` with a top-level `ORDER BY` to
+/// return `count` rows starting at row `offset` (0-based). Returns `None`
+/// for anything else: several statements, no `ORDER BY`, or a clause that
+/// `OFFSET` cannot follow.
+pub fn with_offset(sql: &str, offset: usize, count: usize) -> Option<String> {
+    let mut tokens = lex(sql).ok()?;
+    while tokens.last().is_some_and(|(t, _)| *t == Token::Symbol(';')) {
+        tokens.pop();
+    }
+    let &(_, end) = tokens.last()?;
+    if !matches!(tokens.first(), Some((Token::Word(w), _)) if w == "SELECT" || w == "WITH") {
+        return None;
+    }
+
+    let mut depth = 0usize;
+    let mut selects = 0;
+    let mut ordered = false;
+    for (i, (token, _)) in tokens.iter().enumerate() {
+        let prev = i.checked_sub(1).map(|p| &tokens[p].0);
+        match token {
+            Token::Symbol(';') => return None,
+            Token::Symbol('(') => depth += 1,
+            Token::Symbol(')') => depth = depth.checked_sub(1)?,
+            Token::Word(word) if depth == 0 && prev != Some(&Token::Symbol('.')) => {
+                let word = word.as_str();
+                if word == "SELECT" {
+                    let joined =
+                        matches!(prev, Some(Token::Word(w)) if SET_OPERATORS.contains(&w.as_str()));
+                    if selects > 0 && !joined {
+                        return None;
+                    }
+                    selects += 1;
+                } else if word == "ORDER" {
+                    ordered |= matches!(tokens.get(i + 1), Some((Token::Word(w), _)) if w == "BY");
+                } else {
+                    // Table hints and CASE expressions use these words too.
+                    let starts_statement = i > 0
+                        && (STATEMENT_STARTS.contains(&word)
+                            || STATEMENT_BOUNDARIES.contains(&word))
+                        && !matches!(word, "WITH" | "END" | "ELSE");
+                    if starts_statement || NOT_PAGEABLE.contains(&word) {
+                        return None;
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    if !ordered || selects == 0 {
+        return None;
+    }
+
+    let sql: String = sql.chars().take(end).collect();
+    Some(format!(
+        "{}\nOFFSET {} ROWS FETCH NEXT {} ROWS ONLY",
+        sql, offset, count
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn words(sql: &str) -> Vec<String> {
+        tokenize(sql)
+            .unwrap()
+            .into_iter()
+            .filter_map(|t| match t {
+                Token::Word(w) => Some(w),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn numbers_end_at_the_literal() {
+        assert_eq!(
+            tokenize("1 1.5 .5 1e5 1.5E-3 0x1F 1.2.3").unwrap(),
+            vec![Token::Number; 8]
+        );
+        assert_eq!(words("SELECT 1+2, 3*(4)"), ["SELECT"]);
+    }
+
+    #[test]
+    fn numbers_running_into_names_are_rejected() {
+        for sql in [
+            "SELECT 1DELETE FROM t",
+            "SELECT 1EXEC('DROP TABLE t')",
+            "SELECT 1eDELETE FROM t",
+            "SELECT 1e-DELETE FROM t",
+            "SELECT 1.0UPDATE t SET a = 1",
+            "SELECT 0x00INSERT INTO t VALUES (1)",
+            "SELECT 1_",
+        ] {
+            assert!(tokenize(sql).is_err(), "{}", sql);
+            assert!(check_read_only(sql).is_err(), "{}", sql);
+        }
+    }
+
+    #[test]
+    fn comments_and_strings_are_not_statements() {
+        assert!(check_read_only("SELECT 1 -- DELETE FROM t").is_ok());
+        assert!(check_read_only("SELECT /* DROP /* nested */ TABLE t */ 1").is_ok());
+        assert!(check_read_only("SELECT 'DELETE FROM t', N'EXEC x'").is_ok());
+        assert!(check_read_only("SELECT 'it''s'; DELETE FROM t").is_err());
+        assert!(check_read_only("SELECT 1 /* unterminated").is_err());
+        assert!(check_read_only("SELECT 'unterminated").is_err());
+    }
+
+    #[test]
+    fn quoted_identifiers_are_names() {
+        assert_eq!(
+            tokenize("[a]]b] \"c\"\"d\"").unwrap(),
+            vec![Token::Quoted("a]b".into()), Token::Quoted("c\"d".into())]
+        );
+        assert!(check_read_only("SELECT [delete], \"drop\" FROM [exec]").is_ok());
+        assert!(check_read_only("SELECT * FROM dbo.[update]").is_ok());
+        assert!(check_read_only("[dbo].[proc]").is_err());
+    }
+
+    #[test]
+    fn exec_is_blocked() {
+        assert!(check_read_only("EXEC sp_who").is_err());
+        assert!(check_read_only("SELECT 1 EXECUTE('x')").is_err());
+        assert!(check_read_only("SELECT 1; exec sp_who").is_err());
+        assert!(check_read_only("master.sys.sp_executesql N'SELECT 1'").is_err());
+        assert!(check_read_only("sp_who").is_err());
+        assert!(check_read_only("SELECT * FROM master..xp_cmdshell").is_err());
+    }
+
+    #[test]
+    fn writes_after_if_else_or_begin_are_blocked() {
+        for sql in [
+            "IF 1=1 DISABLE TRIGGER dbo.trg ON dbo.t",
+            "IF 1=1 ENABLE TRIGGER dbo.trg ON dbo.t",
+            "IF 1=1 SEND ON CONVERSATION @h MESSAGE TYPE m",
+            "IF 1=1 SETUSER 'x'",
+            "IF 1=1 MOVE CONVERSATION @h TO @g",
+            "IF 1=1 SELECT 1 ELSE DISABLE TRIGGER ALL ON DATABASE",
+            "BEGIN DIALOG CONVERSATION @h FROM SERVICE a TO SERVICE 'b'",
+            "BEGIN CONVERSATION TIMER (@h) TIMEOUT = 60",
+            "IF 1=1 END CONVERSATION @h",
+            "BEGIN SELECT 1 END CONVERSATION @h",
+        ] {
+            assert!(check_read_only(sql).is_err(), "{}", sql);
+        }
+        assert!(check_read_only("IF 1=1 BEGIN SELECT 1 END ELSE BEGIN SELECT 2 END").is_ok());
+        assert!(check_read_only("SELECT CASE WHEN 1=1 THEN 1 END AS x").is_ok());
+    }
+
+    #[test]
+    fn read_only_batches_pass() {
+        assert!(check_read_only("SELECT name FROM sys.databases ORDER BY name").is_ok());
+        assert!(check_read_only(
+            "DECLARE @n int = 1; WITH c AS (SELECT @n AS n) SELECT * FROM c; PRINT @n"
+        )
+        .is_ok());
+        assert!(check_read_only("SELECT next_value FROM t").is_ok());
+        assert!(check_read_only("SELECT NEXT VALUE FOR s").is_err());
+        assert!(check_read_only("SELECT * INTO t2 FROM t").is_err());
+        assert!(check_read_only("BEGIN TRAN; SELECT 1").is_err());
+    }
+
+    #[test]
+    fn only_reset_session_options_can_be_set() {
+        assert!(check_read_only("DECLARE @n int; SET @n = 1; SELECT @n").is_ok());
+        assert!(check_read_only("SET NOCOUNT ON; SELECT 1").is_ok());
+        assert!(check_read_only("SET STATISTICS IO ON; SELECT 1").is_ok());
+        assert!(check_read_only("SET TRANSACTION ISOLATION LEVEL SNAPSHOT; SELECT 1").is_ok());
+        for sql in [
+            "SET ROWCOUNT 1; SELECT 1",
+            "SET LANGUAGE Deutsch",
+            "SET ANSI_NULLS OFF",
+            "SELECT 1 SET DATEFORMAT dmy",
+            "SET CONTEXT_INFO 0x01",
+            "SET [NOCOUNT] ON",
+        ] {
+            assert!(check_read_only(sql).is_err(), "{}", sql);
+        }
+    }
+
+    #[test]
+    fn early_exits_are_found() {
+        assert!(check_runs_to_end("SELECT 1; RETURN; SELECT 2").is_err());
+        assert!(check_runs_to_end("IF 1 = 1 return").is_err());
+        assert!(check_runs_to_end("GOTO done").is_err());
+        assert!(check_runs_to_end("SELECT [return], t.goto FROM t").is_ok());
+        assert!(check_runs_to_end("SELECT 'RETURN' -- GOTO").is_ok());
+    }
+
+    #[test]
+    fn ordered_selects_are_paged_from_the_offset() {
+        assert_eq!(
+            with_offset("SELECT a FROM t ORDER BY a; -- done", 20, 11).as_deref(),
+            Some("SELECT a FROM t ORDER BY a\nOFFSET 20 ROWS FETCH NEXT 11 ROWS ONLY")
+        );
+        let cte = "WITH c AS (SELECT TOP 5 a FROM t ORDER BY a)\n\
+                   SELECT a, CASE WHEN a > 1 THEN 1 ELSE 0 END FROM c WITH (NOLOCK)\n\
+                   UNION ALL SELECT b, 0 FROM u ORDER BY 1";
+        assert!(with_offset(cte, 0, 10).is_some());
+    }
+
+    #[test]
+    fn other_batches_are_not_paged() {
+        for sql in [
+            "SELECT a FROM t",
+            "SELECT a, ROW_NUMBER() OVER (ORDER BY a) FROM t",
+            "SELECT TOP 10 a FROM t ORDER BY a",
+            "SELECT a FROM t ORDER BY a OFFSET 5 ROWS",
+            "SELECT a FROM t ORDER BY a FOR JSON PATH",
+            "SELECT a FROM t ORDER BY a OPTION (RECOMPILE)",
+            "SELECT 1; SELECT a FROM t ORDER BY a",
+            "SELECT 1 SELECT a FROM t ORDER BY a",
+            "SELECT a FROM t ORDER BY a PRINT 'x'",
+            "DECLARE @n int = 1; SELECT @n ORDER BY 1",
+            "SELECT 'unterminated",
+        ] {
+            assert_eq!(with_offset(sql, 0, 10), None, "{}", sql);
+        }
+    }
+}
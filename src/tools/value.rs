@@ -0,0 +1,412 @@
+use claw::numeric::Numeric;
+use claw::time::{Date, DateTime, DateTime2, DateTimeOffset, SmallDateTime, Time};
+use claw::{ColumnData, ColumnType};
+use serde_json::Value;
+
+/// Render a single cell as text, using the column's SQL type where the
+/// decoded value alone is ambiguous (e.g. `money` arrives as a float).
+/// Returns `None` for SQL `NULL`.
+pub fn render_cell(column_type: ColumnType, data: &ColumnData<'_>) -> Option<String> {
+    match data {
+        ColumnData::U8(v) => v.map(|v| v.to_string()),
+        ColumnData::I16(v) => v.map(|v| v.to_string()),
+        ColumnData::I32(v) => v.map(|v| v.to_string()),
+        ColumnData::I64(v) => v.map(|v| v.to_string()),
+        ColumnData::F32(v) => v.map(|v| match column_type {
+            ColumnType::Money | ColumnType::Money4 => format!("{:.4}", v),
+            _ => v.to_string(),
+        }),
+        ColumnData::F64(v) => v.map(|v| match column_type {
+            ColumnType::Money | ColumnType::Money4 => format!("{:.4}", v),
+            _ => v.to_string(),
+        }),
+        ColumnData::Bit(v) => v.map(|v| if v { "1" } else { "0" }.to_string()),
+        ColumnData::String(v) => v.as_ref().map(|s| s.to_string()),
+        ColumnData::Guid(v) => v.map(|g| g.to_string().to_uppercase()),
+        ColumnData::Binary(v) => v.as_ref().map(|b| hex(b)),
+        ColumnData::Numeric(v) => v.map(format_numeric),
+        ColumnData::Xml(v) => v.as_ref().map(|x| x.to_string()),
+        ColumnData::DateTime(v) => v.map(format_datetime),
+        ColumnData::SmallDateTime(v) => v.map(format_smalldatetime),
+        ColumnData::Time(v) => v.map(format_time),
+        ColumnData::Date(v) => v.map(format_date),
+        ColumnData::DateTime2(v) => v.map(format_datetime2),
+        ColumnData::DateTimeOffset(v) => v.map(format_datetimeoffset),
+    }
+}
+
+/// Binary values longer than this are summarized instead of shown as hex
+/// when cells are limited.
+const MAX_SHOWN_BINARY: usize = 32;
+
+/// Render a cell for output, cutting text longer than `max_chars`
+/// characters with a marker saying how much was dropped, and summarizing
+/// long binary values by their size. Returns the text and whether anything
+/// was cut.
+pub fn render_limited(
+    column_type: ColumnType,
+    data: &ColumnData<'_>,
+    max_chars: Option<usize>,
+) -> (Option<String>, bool) {
+    let max_chars = match max_chars {
+        Some(max) => max,
+        None => return (render_cell(column_type, data), false),
+    };
+    if let ColumnData::Binary(Some(bytes)) = data {
+        if bytes.len() > MAX_SHOWN_BINARY {
+            return (Some(format!("<binary, {} bytes>", bytes.len())), true);
+        }
+    }
+    match render_cell(column_type, data) {
+        Some(text) => {
+            let chars = text.chars().count();
+            if chars <= max_chars {
+                return (Some(text), false);
+            }
+            let kept: String = text.chars().take(max_chars).collect();
+            (
+                Some(format!("{}…[+{} chars]", kept, chars - max_chars)),
+                true,
+            )
+        }
+        None => (None, false),
+    }
+}
+
+/// Convert a single cell to JSON: integers, floats and bits become JSON
+/// numbers and booleans; everything else (including `decimal`, which may
+/// not fit a double exactly) is the text from [`render_cell`].
+pub fn cell_to_json(column_type: ColumnType, data: &ColumnData<'_>) -> Value {
+    limited_cell_to_json(column_type, data, None)
+}
+
+/// [`cell_to_json`] with text cut as by [`render_limited`].
+pub fn limited_cell_to_json(
+    column_type: ColumnType,
+    data: &ColumnData<'_>,
+    max_chars: Option<usize>,
+) -> Value {
+    match data {
+        ColumnData::U8(v) => v.map(Value::from),
+        ColumnData::I16(v) => v.map(Value::from),
+        ColumnData::I32(v) => v.map(Value::from),
+        ColumnData::I64(v) => v.map(Value::from),
+        ColumnData::F32(v) => v.map(Value::from),
+        ColumnData::F64(v) => v.map(Value::from),
+        ColumnData::Bit(v) => v.map(Value::from),
+        other => render_limited(column_type, other, max_chars)
+            .0
+            .map(Value::from),
+    }
+    .unwrap_or(Value::Null)
+}
+
+/// The SQL Server type name reported for a result column. The driver does
+/// not report the size of a nullable integer, float, datetime or money
+/// column, so those keep the neutral TDS names `intn`, `floatn`,
+/// `datetimen` and `moneyn` instead of guessing, e.g., `int` for a
+/// `bigint`.
+pub fn type_name(column_type: ColumnType) -> &'static str {
+    match column_type {
+        ColumnType::Null => "null",
+        ColumnType::Bit | ColumnType::Bitn => "bit",
+        ColumnType::Int1 => "tinyint",
+        ColumnType::Int2 => "smallint",
+        ColumnType::Int4 => "int",
+        ColumnType::Intn => "intn",
+        ColumnType::Int8 => "bigint",
+        ColumnType::Float4 => "real",
+        ColumnType::Float8 => "float",
+        ColumnType::Floatn => "floatn",
+        // Both `money` and a nullable `money` or `smallmoney`.
+        ColumnType::Money => "moneyn",
+        ColumnType::Money4 => "smallmoney",
+        ColumnType::Decimaln => "decimal",
+        ColumnType::Numericn => "numeric",
+        ColumnType::Datetime4 => "smalldatetime",
+        ColumnType::Datetime => "datetime",
+        ColumnType::Datetimen => "datetimen",
+        ColumnType::Daten => "date",
+        ColumnType::Timen => "time",
+        ColumnType::Datetime2 => "datetime2",
+        ColumnType::DatetimeOffsetn => "datetimeoffset",
+        ColumnType::Guid => "uniqueidentifier",
+        ColumnType::BigVarBin => "varbinary",
+        ColumnType::BigBinary => "binary",
+        ColumnType::BigVarChar => "varchar",
+        ColumnType::BigChar => "char",
+        ColumnType::NVarchar => "nvarchar",
+        ColumnType::NChar => "nchar",
+        ColumnType::Xml => "xml",
+        ColumnType::Udt => "udt",
+        ColumnType::Text => "text",
+        ColumnType::NText => "ntext",
+        ColumnType::Image => "image",
+        ColumnType::SSVariant => "sql_variant",
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    let mut out = String::with_capacity(2 + bytes.len() * 2);
+    out.push_str("0x");
+    for b in bytes {
+        out.push_str(&format!("{:02X}", b));
+    }
+    out
+}
+
+/// A decimal's exact digits. `Numeric`'s own `Display` repeats the sign in
+/// the fraction of negative values (`-1.-5` for -1.05).
+fn format_numeric(n: Numeric) -> String {
+    let digits = n.value().unsigned_abs().to_string();
+    let scale = n.scale() as usize;
+    let sign = if n.value() < 0 { "-" } else { "" };
+    if scale == 0 {
+        return format!("{}{}", sign, digits);
+    }
+    let digits = format!("{:0>width$}", digits, width = scale + 1);
+    let (int, frac) = digits.split_at(digits.len() - scale);
+    format!("{}{}.{}", sign, int, frac)
+}
+
+/// Convert a day count since 0001-01-01 into (year, month, day).
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    // Shift to days since 0000-03-01, then apply the standard era algorithm.
+    let z = days + 306;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let d = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let m = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let y = yoe + era * 400 + if m <= 2 { 1 } else { 0 };
+    (y, m, d)
+}
+
+/// Days between 0001-01-01 and 1900-01-01, the epoch of `datetime`.
+const DAYS_TO_1900: i64 = 693_595;
+
+fn format_ymd(days_since_0001: i64) -> String {
+    let (y, m, d) = civil_from_days(days_since_0001);
+    format!("{:04}-{:02}-{:02}", y, m, d)
+}
+
+fn format_hms(total_seconds: u64, fraction: Option<(u64, usize)>) -> String {
+    let h = total_seconds / 3600;
+    let m = (total_seconds / 60) % 60;
+    let s = total_seconds % 60;
+    match fraction {
+        Some((frac, digits)) if digits > 0 => {
+            format!(
+                "{:02}:{:02}:{:02}.{:0width$}",
+                h,
+                m,
+                s,
+                frac,
+                width = digits
+            )
+        }
+        _ => format!("{:02}:{:02}:{:02}", h, m, s),
+    }
+}
+
+fn format_date(date: Date) -> String {
+    format_ymd(date.days() as i64)
+}
+
+fn format_time(time: Time) -> String {
+    let scale = time.scale() as u32;
+    let per_second = 10u64.pow(scale);
+    let increments = time.increments();
+    format_hms(
+        increments / per_second,
+        Some((increments % per_second, scale as usize)),
+    )
+}
+
+fn format_datetime(dt: DateTime) -> String {
+    // `datetime` stores time as 1/300 second ticks; render milliseconds.
+    let millis = (dt.seconds_fragments() as u64 * 1000 + 150) / 300;
+    format!(
+        "{} {}",
+        format_ymd(DAYS_TO_1900 + dt.days() as i64),
+        format_hms(millis / 1000, Some((millis % 1000, 3)))
+    )
+}
+
+fn format_smalldatetime(dt: SmallDateTime) -> String {
+    format!(
+        "{} {}",
+        format_ymd(DAYS_TO_1900 + dt.days() as i64),
+        format_hms(dt.seconds_fragments() as u64 * 60, None)
+    )
+}
+
+fn format_datetime2(dt: DateTime2) -> String {
+    format!("{} {}", format_date(dt.date()), format_time(dt.time()))
+}
+
+fn format_datetimeoffset(dto: DateTimeOffset) -> String {
+    // The wire value is UTC; shift it into the stored offset for display.
+    let utc = dto.datetime2();
+    let offset = dto.offset() as i64;
+    let time = utc.time();
+    let per_minute = 60 * 10u64.pow(time.scale() as u32);
+    let day_minutes = 24 * 60;
+
+    let utc_minutes = (time.increments() / per_minute) as i64;
+    let sub_minute = time.increments() % per_minute;
+    let local_minutes = utc_minutes + offset;
+    let days = utc.date().days() as i64 + local_minutes.div_euclid(day_minutes);
+    let local_minutes = local_minutes.rem_euclid(day_minutes) as u64;
+
+    let local_time = Time::new(local_minutes * per_minute + sub_minute, time.scale());
+    let sign = if offset < 0 { '-' } else { '+' };
+    format!(
+        "{} {} {}{:02}:{:02}",
+        format_ymd(days),
+        format_time(local_time),
+        sign,
+        offset.abs() / 60,
+        offset.abs() % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::borrow::Cow;
+
+    fn text(column_type: ColumnType, data: ColumnData<'_>) -> Option<String> {
+        render_cell(column_type, &data)
+    }
+
+    #[test]
+    fn numbers_render_by_column_type() {
+        assert_eq!(
+            text(ColumnType::Int4, ColumnData::I32(Some(-7))).as_deref(),
+            Some("-7")
+        );
+        assert_eq!(
+            text(ColumnType::Money, ColumnData::F64(Some(1.5))).as_deref(),
+            Some("1.5000")
+        );
+        assert_eq!(
+            text(ColumnType::Float8, ColumnData::F64(Some(1.5))).as_deref(),
+            Some("1.5")
+        );
+        assert_eq!(
+            text(
+                ColumnType::Decimaln,
+                ColumnData::Numeric(Some(Numeric::new_with_scale(-105, 2)))
+            )
+            .as_deref(),
+            Some("-1.05")
+        );
+        for (value, scale, shown) in [(-5, 2, "-0.05"), (12, 0, "12"), (-120, 1, "-12.0")] {
+            assert_eq!(format_numeric(Numeric::new_with_scale(value, scale)), shown);
+        }
+        assert_eq!(
+            text(ColumnType::Bitn, ColumnData::Bit(Some(true))).as_deref(),
+            Some("1")
+        );
+        assert_eq!(text(ColumnType::Intn, ColumnData::I32(None)), None);
+    }
+
+    #[test]
+    fn dates_and_times_render_like_sql_server() {
+        let date = Date::new(738_885); // 2024-01-01
+        assert_eq!(
+            text(ColumnType::Daten, ColumnData::Date(Some(date))).as_deref(),
+            Some("2024-01-01")
+        );
+        assert_eq!(
+            text(
+                ColumnType::Timen,
+                ColumnData::Time(Some(Time::new(45_296_123, 3)))
+            )
+            .as_deref(),
+            Some("12:34:56.123")
+        );
+        // 1900-01-02 00:00:01.003, in 1/300 second ticks.
+        assert_eq!(
+            text(
+                ColumnType::Datetimen,
+                ColumnData::DateTime(Some(DateTime::new(1, 301)))
+            )
+            .as_deref(),
+            Some("1900-01-02 00:00:01.003")
+        );
+        assert_eq!(
+            text(
+                ColumnType::Datetime4,
+                ColumnData::SmallDateTime(Some(SmallDateTime::new(0, 61)))
+            )
+            .as_deref(),
+            Some("1900-01-01 01:01:00")
+        );
+        // 23:30 UTC on 2023-12-31 is 01:30 the next day at +02:00.
+        let utc = DateTime2::new(Date::new(738_884), Time::new((23 * 60 + 30) * 60, 0));
+        assert_eq!(
+            text(
+                ColumnType::DatetimeOffsetn,
+                ColumnData::DateTimeOffset(Some(DateTimeOffset::new(utc, 120)))
+            )
+            .as_deref(),
+            Some("2024-01-01 01:30:00 +02:00")
+        );
+    }
+
+    #[test]
+    fn long_cells_are_cut_with_a_marker() {
+        let long = ColumnData::String(Some(Cow::Borrowed("héllo world")));
+        assert_eq!(
+            render_limited(ColumnType::NVarchar, &long, Some(5)),
+            (Some("héllo…[+6 chars]".to_string()), true)
+        );
+        assert_eq!(
+            render_limited(ColumnType::NVarchar, &long, None),
+            (Some("héllo world".to_string()), false)
+        );
+        let short = ColumnData::Binary(Some(Cow::Borrowed(&[0xAB, 0x01][..])));
+        assert_eq!(
+            render_limited(ColumnType::BigVarBin, &short, Some(100)),
+            (Some("0xAB01".to_string()), false)
+        );
+        let blob = ColumnData::Binary(Some(Cow::Owned(vec![0; 40])));
+        assert_eq!(
+            render_limited(ColumnType::BigVarBin, &blob, Some(100)),
+            (Some("<binary, 40 bytes>".to_string()), true)
+        );
+    }
+
+    #[test]
+    fn json_keeps_numbers_typed_and_decimals_exact() {
+        assert_eq!(
+            cell_to_json(ColumnType::Int8, &ColumnData::I64(Some(5))),
+            Value::from(5)
+        );
+        assert_eq!(
+            cell_to_json(ColumnType::Bit, &ColumnData::Bit(Some(false))),
+            Value::from(false)
+        );
+        assert_eq!(
+            cell_to_json(
+                ColumnType::Numericn,
+                &ColumnData::Numeric(Some(Numeric::new_with_scale(12_345, 4)))
+            ),
+            Value::from("1.2345")
+        );
+        assert_eq!(
+            cell_to_json(ColumnType::NVarchar, &ColumnData::String(None)),
+            Value::Null
+        );
+        assert_eq!(type_name(ColumnType::Money4), "smallmoney");
+        assert_eq!(type_name(ColumnType::Int4), "int");
+        assert_eq!(type_name(ColumnType::Intn), "intn");
+        assert_eq!(type_name(ColumnType::Floatn), "floatn");
+        assert_eq!(type_name(ColumnType::Datetimen), "datetimen");
+        assert_eq!(type_name(ColumnType::Money), "moneyn");
+    }
+}